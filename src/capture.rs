@@ -0,0 +1,229 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+/// The end of the stream is kept this long to find the prompt once over budget
+const TAIL: usize = 64;
+/// The spill file stops growing there
+const SPILL_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Limits on what one evaluation prints
+/// `IHSK_OUTPUT_BYTES` (1MB) and `IHSK_OUTPUT_LINES` (5000),
+/// `IHSK_OUTPUT_INTERRUPT=1` also interrupts the repl when they are reached
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    bytes: usize,
+    lines: usize,
+    /// pid to send SIGINT to
+    interrupt: Option<u32>,
+    /// bytes of the output already scanned
+    scanned: usize,
+    /// newlines among them
+    newlines: usize,
+}
+
+impl Budget {
+    pub fn for_eval(pid: u32) -> Self {
+        let var = |name: &str, default: usize| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            bytes: var("IHSK_OUTPUT_BYTES", 1_000_000),
+            lines: var("IHSK_OUTPUT_LINES", 5_000),
+            interrupt: match std::env::var("IHSK_OUTPUT_INTERRUPT").as_deref() {
+                Ok("1") => Some(pid),
+                _ => None,
+            },
+            scanned: 0,
+            newlines: 0,
+        }
+    }
+
+    /// Where to cut `out` if it is over budget, on a char boundary
+    /// only the bytes added since the last call are scanned for newlines
+    fn cut(&mut self, out: &[u8]) -> Option<usize> {
+        let mut by_lines = None;
+        for (idx, b) in out.iter().enumerate().skip(self.scanned) {
+            if *b == b'\n' {
+                self.newlines += 1;
+                if self.newlines >= self.lines.max(1) {
+                    by_lines = Some(idx + 1);
+                    break;
+                }
+            }
+        }
+        self.scanned = out.len();
+        let mut by_bytes = if out.len() > self.bytes {
+            Some(self.bytes)
+        } else {
+            None
+        };
+        if let Some(cut) = &mut by_bytes {
+            while *cut > 0 && out[*cut] & 0xC0 == 0x80 {
+                *cut -= 1;
+            }
+        }
+        match (by_lines, by_bytes) {
+            (Some(lines), Some(bytes)) => Some(lines.min(bytes)),
+            (cut, None) | (None, cut) => cut,
+        }
+    }
+}
+
+/// What the repl printed up to its prompt
+pub struct Capture {
+    /// the output within budget, it still ends with the prompt
+    pub out: Vec<u8>,
+    /// bytes past the budget, not counting the prompt
+    pub dropped: usize,
+    /// the whole output, once it went over budget
+    pub spill: Option<PathBuf>,
+}
+
+/// Output past the budget, written to the spill file while it is below the limit
+struct Spill {
+    file: Option<File>,
+    written: usize,
+    /// the end of the stream, to find the prompt
+    tail: Vec<u8>,
+}
+
+/// Reads until the output ends with one of the prompt `marks`
+/// past the `budget` the output goes to a spill file instead of memory
+pub fn read_until(reader: &mut impl Read, marks: &[&[u8]], mut budget: Option<Budget>) -> Capture {
+    let mut out = vec![];
+    let mut buf = [0; 512];
+    let mut spill: Option<Spill> = None;
+    let mut dropped = 0;
+    loop {
+        let n = reader.read(&mut buf).unwrap();
+        let chunk = &buf[..n];
+        match &mut spill {
+            None => {
+                out.extend(chunk);
+                let cut = match budget.as_mut().and_then(|budget| budget.cut(&out)) {
+                    Some(cut) => cut,
+                    None if marks.iter().any(|mark| out.ends_with(mark)) => {
+                        return Capture {
+                            out,
+                            dropped: 0,
+                            spill: None,
+                        }
+                    }
+                    None => continue,
+                };
+                let file =
+                    open_spill().and_then(|mut file| file.write_all(&out).ok().map(|_| file));
+                spill = Some(Spill {
+                    file,
+                    written: out.len(),
+                    tail: out[out.len().saturating_sub(TAIL)..].to_vec(),
+                });
+                dropped = out.len() - cut;
+                out.truncate(cut);
+                if let Some(pid) = budget.and_then(|budget| budget.interrupt) {
+                    interrupt(pid);
+                }
+            }
+            Some(spill) => {
+                if let Some(file) = &mut spill.file {
+                    if spill.written < SPILL_LIMIT && file.write_all(chunk).is_ok() {
+                        spill.written += n;
+                    }
+                }
+                dropped += n;
+                spill.tail.extend(chunk);
+                spill.tail.drain(..spill.tail.len().saturating_sub(TAIL));
+            }
+        }
+        let spill = spill.as_ref().unwrap();
+        if let Some(mark) = marks.iter().find(|mark| spill.tail.ends_with(mark)) {
+            // the prompt goes on its own line after the cut
+            match mark.strip_prefix(b"\n") {
+                Some(prompt) if out.ends_with(b"\n") => out.extend(prompt),
+                _ => out.extend(*mark),
+            }
+            dropped = dropped.saturating_sub(mark.len());
+            if let Some(file) = &spill.file {
+                // the prompt isn't output, unless the file stopped before it
+                if spill.written < SPILL_LIMIT {
+                    let _ = file.set_len(spill.written.saturating_sub(mark.len()) as u64);
+                }
+            }
+            return Capture {
+                out,
+                dropped,
+                spill: spill.file.as_ref().map(|_| spill_path()),
+            };
+        }
+    }
+}
+
+/// `~/.cache/ihsk/output.txt`, it holds the last output that went over budget
+pub fn spill_path() -> PathBuf {
+    dirs_next::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("ihsk")
+        .join("output.txt")
+}
+
+fn open_spill() -> Option<File> {
+    let path = spill_path();
+    std::fs::create_dir_all(path.parent()?).ok()?;
+    File::create(path).ok()
+}
+
+fn interrupt(pid: u32) {
+    use nix::{
+        sys::signal::{kill, Signal},
+        unistd::Pid,
+    };
+    let _ = kill(Pid::from_raw(pid as i32), Some(Signal::SIGINT));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(bytes: usize, lines: usize) -> Budget {
+        Budget {
+            bytes,
+            lines,
+            interrupt: None,
+            scanned: 0,
+            newlines: 0,
+        }
+    }
+
+    #[test]
+    fn cut_counts_lines_across_chunks() {
+        let mut budget = budget(1000, 3);
+        let mut out = b"a\nb".to_vec();
+        assert_eq!(budget.cut(&out), None);
+        out.extend(b"\nc");
+        assert_eq!(budget.cut(&out), None);
+        out.extend(b"\nd\ne\n");
+        assert_eq!(budget.cut(&out), Some(6));
+    }
+
+    #[test]
+    fn cut_keeps_chars_whole() {
+        let mut budget = budget(3, 100);
+        assert_eq!(budget.cut("aé".as_bytes()), None);
+        assert_eq!(budget.cut("aéé".as_bytes()), Some(3));
+        let mut budget = self::budget(2, 100);
+        assert_eq!(budget.cut("aéé".as_bytes()), Some(1));
+    }
+
+    #[test]
+    fn read_until_stops_at_the_prompt() {
+        let text = "1\n2\n> ";
+        let capture = read_until(&mut text.as_bytes(), &[b"\n> "], Some(budget(1000, 5)));
+        assert_eq!(capture.out, text.as_bytes());
+        assert_eq!(capture.dropped, 0);
+        assert!(capture.spill.is_none());
+    }
+}
@@ -0,0 +1,167 @@
+use std::ops::Range;
+
+use crate::diagnostic::{Diagnostic, Span};
+use crate::token::{Class, Token};
+use crate::utils;
+
+/// An input as typed and as sent to the repl
+pub struct Cell {
+    pub typed: String,
+    /// the input with its references replaced by the names of the results
+    pub source: String,
+    /// byte ranges of each reference in `typed` and of its name in `source`
+    references: Vec<(Range<usize>, Range<usize>)>,
+}
+
+/// `%out 3` and `_3` refer to the result of cell 3, they become `name(3)`
+/// strings and comments are left alone
+pub fn resolve(typed: &str, tokens: &[Token], name: impl Fn(usize) -> String) -> Cell {
+    let mut cell = Cell {
+        typed: typed.to_owned(),
+        source: String::with_capacity(typed.len()),
+        references: vec![],
+    };
+    // code between the literals, `%out 3` spans several tokens
+    let mut code_start = 0;
+    for token in tokens {
+        if matches!(token.class, Class::String | Class::Char | Class::Comment) {
+            cell.references(code_start..token.start, &name);
+            cell.source.push_str(token.text(typed));
+            code_start = token.end;
+        }
+    }
+    cell.references(code_start..typed.len(), &name);
+    cell
+}
+
+impl Cell {
+    /// Copies the `code` range of the typed input to the source, resolving the references
+    fn references(&mut self, code: Range<usize>, name: &impl Fn(usize) -> String) {
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+        let text = &self.typed[code.clone()];
+        let mut idx = 0;
+        while idx < text.len() {
+            let rest = &text[idx..];
+            let after_word = matches!(text[..idx].chars().next_back(), Some(c) if is_word_char(c));
+            let digits_at = if after_word {
+                None
+            } else if let Some(arg) = rest.strip_prefix("%out") {
+                let spaces = arg.len() - arg.trim_start_matches(' ').len();
+                Some(4 + spaces).filter(|_| spaces > 0)
+            } else if rest.starts_with('_') {
+                Some(1)
+            } else {
+                None
+            };
+            if let Some(digits_at) = digits_at {
+                let digits = rest[digits_at..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len() - digits_at);
+                let end = digits_at + digits;
+                let ends_word = !matches!(rest[end..].chars().next(), Some(c) if is_word_char(c));
+                if let Ok(number) = rest[digits_at..end].parse::<usize>() {
+                    if number > 0 && ends_word {
+                        let at = self.source.len();
+                        self.source.push_str(&name(number));
+                        let typed_at = code.start + idx;
+                        self.references
+                            .push((typed_at..typed_at + end, at..self.source.len()));
+                        idx += end;
+                        continue;
+                    }
+                }
+            }
+            let c = rest.chars().next().unwrap();
+            self.source.push(c);
+            idx += c.len_utf8();
+        }
+    }
+
+    /// Moves the spans found in the source to the same text in the typed input
+    /// a span on a result name covers the whole reference
+    pub fn retarget(&self, diagnostics: &mut [Diagnostic]) {
+        if self.references.is_empty() {
+            return;
+        }
+        for diagnostic in diagnostics {
+            if let Some(span) = &mut diagnostic.span {
+                *span = self.typed_span(*span);
+            }
+        }
+    }
+
+    fn typed_span(&self, span: Span) -> Span {
+        let start = self.typed_offset(utils::offset(&self.source, span.start), false);
+        let end = self.typed_offset(utils::offset(&self.source, span.end), true);
+        Span {
+            start: utils::line_col(&self.typed, start),
+            end: utils::line_col(&self.typed, end),
+        }
+    }
+
+    /// The offset in `typed` of a char in `source`, `last` for the end of a span
+    fn typed_offset(&self, offset: usize, last: bool) -> usize {
+        let mut typed_offset = offset;
+        for (typed, source) in &self.references {
+            if offset < source.start {
+                break;
+            }
+            if offset < source.end {
+                return if last { typed.end - 1 } else { typed.start };
+            }
+            typed_offset = offset - source.end + typed.end;
+        }
+        typed_offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every char is code
+    fn cell(typed: &str) -> Cell {
+        resolve(typed, &[], |number| format!("result{}", number))
+    }
+
+    #[test]
+    fn references_become_result_names() {
+        assert_eq!(cell("%out 3 + _12").source, "result3 + result12");
+        // inside a word, without a number, or cell 0
+        assert_eq!(cell("x_3 _x %out3 _0 _3a").source, "x_3 _x %out3 _0 _3a");
+    }
+
+    #[test]
+    fn literals_are_left_alone() {
+        let string = Token {
+            class: Class::String,
+            start: 2,
+            end: 6,
+            closed: true,
+        };
+        let cell = resolve(r#"f "_3" _3"#, &[string], |number| {
+            format!("result{}", number)
+        });
+        assert_eq!(cell.source, r#"f "_3" result3"#);
+    }
+
+    fn span(start: (usize, usize), end: (usize, usize)) -> Span {
+        Span { start, end }
+    }
+
+    #[test]
+    fn spans_move_back_to_the_typed_input() {
+        let cell = cell("f %out 3 + x\n  g _12 y");
+        assert_eq!(cell.source, "f result3 + x\n  g result12 y");
+        // `x` after a reference
+        assert_eq!(
+            cell.typed_span(span((1, 13), (1, 13))),
+            span((1, 12), (1, 12))
+        );
+        // a span on the name covers the reference
+        assert_eq!(cell.typed_span(span((1, 3), (1, 9))), span((1, 3), (1, 8)));
+        assert_eq!(cell.typed_span(span((2, 5), (2, 14))), span((2, 5), (2, 9)));
+        // before any reference
+        assert_eq!(cell.typed_span(span((1, 1), (1, 1))), span((1, 1), (1, 1)));
+    }
+}
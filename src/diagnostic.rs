@@ -0,0 +1,111 @@
+use crate::highlight;
+use crate::term::{self, Colors};
+use crate::theme::{self, Role, THEME};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// 1-based lines and char columns inside the user's input, the end column is included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+/// An error or warning reported by a backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// None if the position couldn't be mapped back to the input
+    pub span: Option<Span>,
+    pub message: String,
+    /// `Perhaps you meant ..` hints
+    pub suggestions: Vec<String>,
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+/// Errors first then warnings, each one with the input lines it points at
+/// the spanned part underlined in the color of its severity
+pub fn render(diagnostics: &[Diagnostic], source: &str) -> String {
+    let mut diagnostics: Vec<&Diagnostic> = diagnostics.iter().collect();
+    diagnostics.sort_by_key(|diagnostic| diagnostic.severity);
+    let mut rendered = String::new();
+    for (idx, diagnostic) in diagnostics.iter().enumerate() {
+        // a blank line between the errors and the warnings
+        if idx > 0 && diagnostics[idx - 1].severity != diagnostic.severity {
+            rendered.push('\n');
+        }
+        rendered.push_str(&render_one(diagnostic, source));
+    }
+    rendered
+}
+
+fn render_one(diagnostic: &Diagnostic, source: &str) -> String {
+    let (label, role) = match diagnostic.severity {
+        Severity::Error => ("error:", Role::Error),
+        Severity::Warning => ("warning:", Role::Warning),
+    };
+    let mut rendered = format!("{} {}\n", theme::paint(label, role), diagnostic.message);
+    if let Some(span) = diagnostic.span {
+        for (idx, line) in source.lines().enumerate() {
+            let row = idx + 1;
+            if row < span.start.0 || row > span.end.0 {
+                continue;
+            }
+            let chars = line.chars().count();
+            let from = if row == span.start.0 { span.start.1 } else { 1 };
+            let to = if row == span.end.0 { span.end.1 } else { chars };
+            rendered.push_str(&theme::paint(&format!("{:>4} | ", row), Role::Hint));
+            rendered.push_str(&underline(line, from, to, role));
+            rendered.push('\n');
+        }
+    }
+    for suggestion in &diagnostic.suggestions {
+        rendered.push_str(&format!(
+            "{} {}\n",
+            theme::paint("  help:", Role::Hint),
+            suggestion
+        ));
+    }
+    rendered
+}
+
+/// `line` with the chars from column `from` to `to` (1-based, included) underlined
+/// without colors, a line of `^` under the span instead
+fn underline(line: &str, from: usize, to: usize, role: Role) -> String {
+    let byte = |col: usize| {
+        line.char_indices()
+            .nth(col.saturating_sub(1))
+            .map_or(line.len(), |(idx, _)| idx)
+    };
+    let (start, end) = (byte(from), byte(to + 1));
+    if start >= end {
+        return line.to_owned();
+    }
+    if *term::COLORS == Colors::Plain {
+        return format!(
+            "{}\n     | {}{}",
+            line,
+            " ".repeat(from.saturating_sub(1)),
+            "^".repeat(line[start..end].chars().count())
+        );
+    }
+    let sgr = match THEME.style(role) {
+        Some(sgr) => format!("{};4", sgr),
+        None => "4".to_owned(),
+    };
+    format!(
+        "{}{}{}",
+        &line[..start],
+        highlight::paint_str(&line[start..end], &sgr),
+        &line[end..]
+    )
+}
@@ -1,116 +1,384 @@
-use std::io::{prelude::*, BufReader};
-use std::process::Stdio;
+use rustyline::validate::ValidationResult;
 use std::sync::mpsc;
 
+use crate::diagnostic::Diagnostic;
+use crate::highlight;
+use crate::query::{self, Backend, Evaluation, Querier, Request};
+use crate::theme::Role;
+use crate::token::{self, Class, Token};
+use crate::utils;
+
+mod diagnostics;
+mod lexer;
+
 const PRELUDE_MARK1: &[u8] = b"Prelude> ";
 
-pub fn ghci(rx_in: mpsc::Receiver<String>, tx_out: mpsc::Sender<String>) {
-    let mut process = std::process::Command::new("ghci")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .unwrap();
-    let pid = process.id();
-    let mut stdout = BufReader::new(process.stdout.as_mut().unwrap());
-
-    let mut out = vec![];
-    let mut buf = [0; 512];
-
-    let mut read = |out: &mut Vec<u8>, mut buf: &mut [u8]| loop {
-        let n = stdout.read(&mut buf).unwrap();
-        out.extend(buf.iter().take(n));
-        if out.ends_with(PRELUDE_MARK1) {
-            break;
+pub fn ghci(rx_in: mpsc::Receiver<Request>, tx_out: mpsc::Sender<Evaluation>) {
+    query::serve::<Ghci>(rx_in, tx_out)
+}
+
+pub struct Ghci;
+
+impl Backend for Ghci {
+    const COMMAND: &'static str = "ghci";
+    const MARKS: &'static [&'static [u8]] = &[PRELUDE_MARK1];
+    const SETUP: &'static [&'static str] = &[
+        // fix the prompt to our mark
+        // so the prompt doesn't change when importing module
+        ":set prompt \"Prelude> \"\n",
+        // multi line inputs are sent as `:{ :}` blocks, hide their continuation prompt
+        ":set prompt-cont \"\"\n",
+    ];
+
+    fn tokens(src: &str) -> Vec<Token> {
+        lexer::lex(src)
+    }
+
+    /// a shell command, `it` is left alone
+    fn sync(mark: &str) -> String {
+        format!(":!echo {} 1>&2\n", mark)
+    }
+
+    fn prepare(source: &str, _number: usize) -> String {
+        prepare(source)
+    }
+
+    fn highlight_output(out: &str) -> String {
+        highlight_output(out)
+    }
+
+    /// errors and warnings are reported on stderr
+    fn diagnostics(out: &str, err: &str, source: &str) -> (Vec<Diagnostic>, String, String) {
+        let (diagnostics, rest) = diagnostics::parse(err, source);
+        (diagnostics, out.to_owned(), rest)
+    }
+
+    fn failed(_out: &str, err: &str) -> bool {
+        err.contains("*** Exception")
+    }
+
+    /// `it` is overwritten by the next evaluation, an interrupted one leaves the old `it`
+    fn bind(out: &str, evaluation: &Evaluation, source: &str, number: usize) -> Option<String> {
+        let interrupted =
+            out.contains("Interrupted.") || evaluation.output.contains("Interrupted.");
+        if evaluation.failed || interrupted || !is_expression(source) {
+            return None;
         }
-    };
+        Some(format!("let {} = it\n", Self::cell_name(number)))
+    }
+}
+
+/// Keywords starting a declaration or an import, they don't set `it`
+const DECLARATIONS: &[&str] = &[
+    "import", "data", "type", "newtype", "class", "instance", "deriving", "default", "foreign",
+    "infix", "infixl", "infixr", "pattern",
+];
 
-    //read welcome message
-    read(&mut out, &mut buf);
-
-    // fix the prompt to our mark
-    // so the prompt doesn't change when importing module
-    process
-        .stdin
-        .as_mut()
-        .unwrap()
-        .write_all(b":set prompt \"Prelude> \"\n")
-        .unwrap();
-
-    // read the new prompt line
-    read(&mut out, &mut buf);
-
-    let (tx_err, rx_err) = mpsc::channel();
-    let mut stderr = process.stderr.take();
-    std::thread::spawn(move || {
-        let mut err = [0; 500];
-        loop {
-            let n = stderr.as_mut().unwrap().read(&mut err).unwrap();
-            let _ = tx_err.send(String::from_utf8(err[..n].to_vec()).unwrap());
+/// Does ghci bind `it` after this input, it does for expressions and `do` statements without `<-`
+/// a `=` or `<-` before any nested block is a binding or a declaration
+fn is_expression(source: &str) -> bool {
+    let tokens = lexer::lex(source);
+    let mut significant = tokens.iter().filter(|token| !token.is_trivia());
+    let first = match significant.next() {
+        Some(first) => first.text(source),
+        None => return false,
+    };
+    if first.starts_with(':') || DECLARATIONS.contains(&first) {
+        return false;
+    }
+    // `let x = 1` but not `let x = 1 in x`
+    if first == "let" {
+        return tokens.iter().any(|token| token.text(source) == "in");
+    }
+    let mut depth = 0;
+    for token in tokens.iter().filter(|token| !token.is_trivia()) {
+        match (token.class, token.text(source)) {
+            (Class::OpenBracket, _) => depth += 1,
+            (Class::CloseBracket, _) => depth -= 1,
+            (_, "do") | (_, "let") | (_, "case") | (_, "where") | (_, "\\") => break,
+            (_, "=") | (_, "<-") if depth == 0 => return false,
+            _ => (),
         }
-    });
+    }
+    true
+}
+
+/// Multi line input is wrapped in `:{ :}` so ghci sees the layout
+fn prepare(inp: &str) -> String {
+    let lines: Vec<&str> = inp.lines().collect();
+    if lines.len() < 2 {
+        return inp.to_owned() + "\n";
+    }
+    // lines separated with `;` by hand (Ctrl-s) still work as one line
+    if lines[..lines.len() - 1]
+        .iter()
+        .all(|line| line.trim_end().ends_with(';'))
+    {
+        return inp.replace("\n", "") + "\n";
+    }
+    format!(":{{\n{}\n:}}\n", inp.trim_end())
+}
+
+/// Tokens after which the definition can't be finished
+const LAYOUT_OPENERS: &[&str] = &[
+    "where", "do", "mdo", "of", "let", "in", "if", "then", "else", "=", "->", "|",
+];
+
+/// Asks for more lines while the input is unfinished
+/// - inside a `{- -}` comment, a string gap or brackets
+/// - after a layout keyword or `=`
+/// - while the indented block goes on, an empty line ends it
+pub fn validate(input: &str) -> ValidationResult {
+    if input.starts_with(':') {
+        return ValidationResult::Valid(None);
+    }
+    let tokens = lexer::lex(input);
+    // comments and `\` string gaps go on over the next line
+    let continues = |token: &Token| {
+        token.class == Class::Comment || token.text(input).trim_end().ends_with('\\')
+    };
+    if let Some(result) = token::check_open(input, &tokens, continues) {
+        return result;
+    }
+
+    let last = match token::last_significant(&tokens) {
+        Some(last) => last,
+        None => return ValidationResult::Valid(None),
+    };
+    let last_text = &input[last.start..last.end];
+    if LAYOUT_OPENERS.contains(&last_text) || input[..last.end].ends_with("\\case") {
+        return ValidationResult::Incomplete;
+    }
+
+    let last_line = input.rsplit('\n').next().unwrap_or_default();
+    if input.contains('\n')
+        && last_line.starts_with(char::is_whitespace)
+        && !last_line.trim().is_empty()
+    {
+        return ValidationResult::Incomplete;
+    }
+    ValidationResult::Valid(None)
+}
+
+/// Identifiers can contain `'` and be qualified `Data.List.foldl'`
+/// the whole line is the word when completing a `:command`
+pub fn word_start(line: &str, pos: usize) -> usize {
+    if line.starts_with(':') && !line[..pos].contains(' ') {
+        return 0;
+    }
+    utils::word_start(line, pos, is_word_char)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\'' || c == '.'
+}
+
+/// The identifier the cursor is on
+pub fn identifier_at(line: &str, pos: usize) -> &str {
+    let start = utils::word_start(line, pos, is_word_char);
+    let end = line[pos..]
+        .find(|c| !is_word_char(c))
+        .map_or(line.len(), |len| pos + len);
+    // `f.` is a composition not a qualified name
+    line[start..end].trim_end_matches('.')
+}
+
+/// The identifier the cursor is in, or the one before the spaces the cursor is after
+/// None while the cursor is at the end of a word, it is still being typed
+pub fn finished_identifier(line: &str, pos: usize) -> Option<&str> {
+    let at_end = |pos: usize| !line[pos..].starts_with(is_word_char);
+    let typed = line[..pos].trim_end();
+    let pos = if !at_end(pos) {
+        pos
+    } else if typed.len() < pos && at_end(typed.len()) {
+        typed.len()
+    } else {
+        return None;
+    };
+    // `:type` is a ghci command
+    if line[..utils::word_start(line, pos, is_word_char)].ends_with(':') {
+        return None;
+    }
+    Some(identifier_at(line, pos)).filter(|identifier| !identifier.is_empty())
+}
+
+/// Ask ghci for the type of `identifier` (`map` => `(a -> b) -> [a] -> [b]`)
+/// unknown names are reported on stderr, the answer is empty then
+pub fn type_of(querier: &Querier, identifier: &str) -> Option<String> {
+    let out = querier.query(&format!(":type {}", identifier))?;
+    let ty = &out[out.find(" :: ")? + 4..];
+    // long types are wrapped over several lines
+    Some(ty.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Ask ghci for the in scope names (identifiers, modules, commands) starting with `word`
+pub fn complete(querier: &Querier, word: &str) -> Vec<String> {
+    let out = match querier.query(&format!(":complete repl {:?}", word)) {
+        Some(out) => out,
+        None => return vec![],
+    };
+    // the first line is a header: `shown total "unused prefix"`
+    // then each candidate is printed as a haskell string
+    out.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let line = line.trim();
+            Some(line.strip_prefix('"')?.strip_suffix('"')?.to_owned())
+        })
+        .collect()
+}
+
+pub fn highlight(line: &str, pos: usize) -> String {
+    let tokens = lexer::lex(line);
+    highlight::paint(line, &tokens, Some(pos), |token| style(line, token))
+}
 
-    ctrlc::set_handler(move || {
-        use nix::{
-            sys::signal::{kill, Signal},
-            unistd::Pid,
+/// Constructors printed like constants
+const CONSTANTS: &[&str] = &["True", "False", "Nothing", "LT", "EQ", "GT"];
+
+/// `show` output: literals, constructors and record fields
+pub fn highlight_output(out: &str) -> String {
+    if highlight::is_colored(out) {
+        return out.to_owned();
+    }
+    let tokens = lexer::lex(out);
+    let fields = highlight::keys(out, &tokens, "=");
+    highlight::paint(out, &tokens, None, |token| {
+        let role = match token.class {
+            Class::Identifier if fields.contains(&token.start) => Role::Field,
+            Class::Type if CONSTANTS.contains(&token.text(out)) => Role::Constant,
+            Class::Type => Role::Type,
+            Class::String => Role::String,
+            Class::Char => Role::Char,
+            Class::Number => Role::Number,
+            _ => return None,
         };
-        let _ = kill(Pid::from_raw(pid as i32), Some(Signal::SIGINT));
+        Some(role)
     })
-    .expect("Error setting Ctrl-C handler");
-
-    loop {
-        out.clear();
-        let inp = match rx_in.recv() {
-            Ok(inp) => inp,
-            // program has ended
-            _ => break,
-        };
+}
 
-        process
-            .stdin
-            .as_mut()
-            .unwrap()
-            .write_all(inp.as_bytes())
-            .unwrap();
+fn style(src: &str, token: &Token) -> Option<Role> {
+    let text = token.text(src);
+    let role = match token.class {
+        Class::Keyword => match text {
+            "where" => Role::Special,
+            "data" | "type" | "newtype" | "class" | "instance" => Role::Declaration,
+            "case" | "of" | "if" | "then" | "else" => Role::Conditional,
+            _ => Role::Keyword,
+        },
+        Class::Operator => match text {
+            "." | "$" => Role::Special,
+            _ => Role::Operator,
+        },
+        Class::Punctuation if text == ";" || text == "," => Role::Punctuation,
+        Class::Type => Role::Type,
+        Class::String => Role::String,
+        Class::Char => Role::Char,
+        Class::Number => Role::Number,
+        Class::Comment => Role::Comment,
+        _ => return None,
+    };
+    Some(role)
+}
 
-        read(&mut out, &mut buf);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::{is_incomplete, is_valid};
 
-        let out = String::from_utf8(out.to_vec()).unwrap();
-        let end = out.rfind("Prelude> ").unwrap();
+    #[test]
+    fn layout_keywords_ask_for_more() {
+        assert!(is_incomplete(validate("let f x = case x of")));
+        assert!(is_incomplete(validate("f x = y where")));
+        assert!(is_incomplete(validate("main = do")));
+        assert!(is_incomplete(validate("let")));
+        assert!(is_incomplete(validate("f x =")));
+        assert!(is_incomplete(validate("f = \\case")));
+    }
 
-        //Note!!! sometimes the output starts with space????
-        let out = out[..end].to_owned();
+    #[test]
+    fn open_blocks_ask_for_more() {
+        assert!(is_incomplete(validate("{- a")));
+        assert!(is_incomplete(validate("map (+ 1)\n  [")));
+        assert!(is_incomplete(validate("f x = do\n  print x")));
+    }
 
-        let err: String = rx_err.try_iter().collect();
-        tx_out.send(out + &err).unwrap();
+    #[test]
+    fn literals_hide_brackets() {
+        assert!(is_valid(validate("putStrLn \"(\"")));
+        assert!(is_valid(validate("'('")));
+        assert!(is_valid(validate("1 + 1 -- (")));
+        assert!(is_valid(validate("f x = do\n  print x\n")));
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(matches!(validate("(1]"), ValidationResult::Invalid(_)));
+        assert!(matches!(validate("\"abc"), ValidationResult::Invalid(_)));
+    }
+
+    #[test]
+    fn blocks_are_sent_between_braces() {
+        assert_eq!(prepare("1 + 1"), "1 + 1\n");
+        assert_eq!(
+            prepare("f x = do\n  print x"),
+            ":{\nf x = do\n  print x\n:}\n"
+        );
+    }
+
+    #[test]
+    fn only_finished_expressions_are_bound() {
+        let evaluation = |output: &str| Evaluation {
+            output: output.to_owned(),
+            failed: false,
+            spill: None,
+            stats: None,
+        };
+        assert_eq!(
+            Ghci::bind("2\n", &evaluation("2\n"), "1 + 1", 3).as_deref(),
+            Some("let _3 = it\n")
+        );
+        assert_eq!(Ghci::bind("", &evaluation(""), "x = 1", 3), None);
+        assert_eq!(
+            Ghci::bind(
+                "Interrupted.\n",
+                &evaluation("Interrupted.\n"),
+                "length [1..]",
+                3
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn types_are_asked_for_finished_identifiers() {
+        assert_eq!(finished_identifier("foldr", 5), None);
+        assert_eq!(finished_identifier("foldr ", 6), Some("foldr"));
+        assert_eq!(finished_identifier("foldr (+) 0", 2), Some("foldr"));
+        assert_eq!(finished_identifier("Data.List.sort xs", 17), None);
+        assert_eq!(
+            finished_identifier("Data.List.sort xs", 3),
+            Some("Data.List.sort")
+        );
+        assert_eq!(finished_identifier("(f . g) ", 8), None);
+        assert_eq!(finished_identifier(":type ", 6), None);
     }
-}
 
-pub fn highlight(line: &str, _pos: usize) -> String {
-    line.replace(";", "\x1b[1;33m;\x1b[0m")
-        //deriving
-        .replace("deriv", "\x1b[1;32mderiv\x1b[0m")
-        .replace("where", "\x1b[1;31mwhere\x1b[0m")
-        .replace("data", "\x1b[1;34mdata\x1b[0m")
-        .replace("case", "\x1b[1;36mcase\x1b[0m")
-        .replace("else", "\x1b[1;33melse\x1b[0m")
-        .replace("then", "\x1b[1;33mthen\x1b[0m")
-        .replace("let", "\x1b[1;32mlet\x1b[0m")
-        .replace("in", "\x1b[1;32min\x1b[0m")
-        .replace("of", "\x1b[1;36mof\x1b[0m")
-        .replace("if", "\x1b[1;33mif\x1b[0m")
-        .replace("=", "\x1b[1;33m=\x1b[0m")
-        .replace("+", "\x1b[1;33m+\x1b[0m")
-        .replace("-", "\x1b[1;33m-\x1b[0m")
-        .replace("*", "\x1b[1;33m*\x1b[0m")
-        .replace("/", "\x1b[1;33m/\x1b[0m")
-        .replace("(", "\x1b[1;33m(\x1b[0m")
-        .replace(")", "\x1b[1;33m)\x1b[0m")
-        .replace(">", "\x1b[1;33m>\x1b[0m")
-        .replace("<", "\x1b[1;33m<\x1b[0m")
-        .replace("^", "\x1b[1;33m^\x1b[0m")
-        .replace("|", "\x1b[1;33m|\x1b[0m")
-        .replace(".", "\x1b[1;31m.\x1b[0m")
-        .replace("$", "\x1b[1;31m$\x1b[0m")
+    #[test]
+    fn highlighting_keeps_the_text() {
+        for line in [
+            "let rec = 1 in rec + 2",
+            "map (\\x -> x * 2) [1..10] -- done",
+            "f \"(\" 'a' {- (( -} Data.Map.insert",
+            "λx → x ∷ Int",
+            "case x of\n  Just y -> y",
+        ] {
+            for pos in [0, line.len() / 2, line.len()] {
+                if line.is_char_boundary(pos) {
+                    assert_eq!(highlight::visible(&highlight(line, pos)), line);
+                }
+            }
+            assert_eq!(highlight::visible(&highlight_output(line)), line);
+        }
+    }
 }
@@ -0,0 +1,279 @@
+use crate::diagnostic::{Diagnostic, Severity, Span};
+
+/// Splits ghci stderr into its `<interactive>:3:5: error:` blocks
+/// and the rest (exceptions, `putStrLn` to stderr..) kept as is
+/// `source` is the input as typed, spans are mapped back onto its lines
+pub fn parse(err: &str, source: &str) -> (Vec<Diagnostic>, String) {
+    let mut diagnostics = vec![];
+    let mut rest = String::new();
+    let lines: Vec<&str> = err.lines().collect();
+    let mut idx = 0;
+    while idx < lines.len() {
+        let line = lines[idx];
+        idx += 1;
+        let (severity, span, head) = match header(line) {
+            Some(header) => header,
+            None => {
+                rest.push_str(line);
+                rest.push('\n');
+                continue;
+            }
+        };
+        // the block is indented, an empty line can be part of it
+        let start = idx;
+        while idx < lines.len() {
+            let indented = lines[idx].starts_with(' ') || is_snippet(lines[idx]);
+            let empty_inside = lines[idx].is_empty()
+                && matches!(lines.get(idx + 1), Some(next) if next.starts_with(' '));
+            if !indented && !empty_inside {
+                break;
+            }
+            idx += 1;
+        }
+        diagnostics.push(diagnostic(severity, span, head, &lines[start..idx], source));
+        // the empty line separating the blocks
+        if lines.get(idx) == Some(&"") {
+            idx += 1;
+        }
+    }
+    (diagnostics, rest)
+}
+
+/// `5 | foo 1` lines quoting the source
+fn is_snippet(line: &str) -> bool {
+    match line.find('|') {
+        Some(bar) => line[..bar].trim().chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// A backend line and column range, before mapping it to the input
+type RawSpan = ((usize, usize), (usize, usize));
+
+/// `<interactive>:3:5: error:` `<interactive>:3:5-9: warning: [-Wx]` `<interactive>:(1,1)-(2,5): error:`
+fn header(line: &str) -> Option<(Severity, Option<RawSpan>, &str)> {
+    let rest = line.strip_prefix("<interactive>:")?;
+    let (position, rest) = rest.split_at(rest.find(": ")?);
+    let rest = rest[2..].trim_start();
+    let (severity, head) = if let Some(head) = rest.strip_prefix("error") {
+        (Severity::Error, head)
+    } else if let Some(head) = rest.strip_prefix("warning") {
+        (Severity::Warning, head)
+    } else {
+        return None;
+    };
+    let head = head.strip_prefix(':').unwrap_or(head).trim();
+    Some((severity, parse_position(position), head))
+}
+
+/// `3:5` `3:5-9` `(1,1)-(2,5)`
+fn parse_position(position: &str) -> Option<RawSpan> {
+    let number = |s: &str| s.trim().parse::<usize>().ok();
+    if let Some(position) = position.strip_prefix('(') {
+        let (start, end) = position.split_at(position.find(")-(")?);
+        let end = end[3..].strip_suffix(')')?;
+        let pair = |s: &str| {
+            let comma = s.find(',')?;
+            Some((number(&s[..comma])?, number(&s[comma + 1..])?))
+        };
+        return Some((pair(start)?, pair(end)?));
+    }
+    let mut parts = position.splitn(2, ':');
+    let line = number(parts.next()?)?;
+    let cols = parts.next()?;
+    let (start, end) = match cols.find('-') {
+        Some(dash) => (number(&cols[..dash])?, number(&cols[dash + 1..])?),
+        None => (number(cols)?, number(cols)?),
+    };
+    Some(((line, start), (line, end)))
+}
+
+/// The body is made of `•` items, the `Perhaps ..` and `Suggested fix:` ones are suggestions
+/// the `3 | foo` source snippet tells where the input starts in ghci line numbers
+fn diagnostic(
+    severity: Severity,
+    span: Option<RawSpan>,
+    head: &str,
+    body: &[&str],
+    source: &str,
+) -> Diagnostic {
+    // the text of each `•` item, and whether it is a suggestion
+    let mut items: Vec<(String, bool)> = vec![];
+    // the items after a `Suggested fixes:` line are all suggestions
+    let mut fixes = false;
+    let mut snippet = None;
+    let mut carets = 0;
+    for line in body {
+        let trimmed = line.trim();
+        if is_snippet(line) {
+            let bar = trimmed.find('|').unwrap();
+            let text = trimmed[bar + 1..].strip_prefix(' ').unwrap_or("");
+            match trimmed[..bar].trim().parse::<usize>() {
+                Ok(number) => snippet = snippet.or(Some((number, text))),
+                // `  | ^^^` under the source
+                Err(_) if carets == 0 => carets = text.trim().chars().filter(|&c| c == '^').count(),
+                Err(_) => (),
+            }
+            continue;
+        }
+        let (text, bullet) = match trimmed.strip_prefix('•') {
+            Some(item) => (item.trim(), true),
+            None => (trimmed, false),
+        };
+        // ghci 9.4 puts `Suggested fix:` on its own line, without a bullet
+        let fix = text
+            .strip_prefix("Suggested fixes:")
+            .or_else(|| text.strip_prefix("Suggested fix:"));
+        if let Some(fix) = fix {
+            fixes = true;
+            items.push((fix.trim().to_owned(), true));
+        } else if bullet || text.starts_with("Perhaps") {
+            items.push((text.to_owned(), fixes || text.starts_with("Perhaps")));
+        } else if let Some((last, _)) = items.last_mut() {
+            if last.is_empty() {
+                *last = text.to_owned();
+            } else {
+                last.push('\n');
+                last.push_str(&format!("    {}", text));
+            }
+        } else if !text.is_empty() {
+            items.push((text.to_owned(), false));
+        }
+    }
+
+    let (suggestions, message): (Vec<_>, Vec<_>) = items
+        .into_iter()
+        .filter(|(item, _)| !item.is_empty())
+        .partition(|(_, suggestion)| *suggestion);
+    let suggestions = suggestions.into_iter().map(|(item, _)| item).collect();
+    let message: Vec<String> = message.into_iter().map(|(item, _)| item).collect();
+    let mut lines = message;
+    match lines.first_mut() {
+        // `[-Wtype-defaults]` goes after the text it is about
+        Some(first) if head.starts_with('[') => {
+            let end = first.find('\n').unwrap_or(first.len());
+            first.insert_str(end, &format!(" {}", head));
+        }
+        _ if !head.is_empty() => lines.insert(0, head.to_owned()),
+        _ => (),
+    }
+    // ghci gives only the start of a multi char span, the carets show its length
+    let span = span.map(|(start, end)| {
+        if start == end && carets > 1 {
+            (start, (end.0, end.1 + carets - 1))
+        } else {
+            (start, end)
+        }
+    });
+    Diagnostic {
+        severity,
+        span: span.and_then(|span| map_span(span, snippet, source)),
+        message: lines.join("\n    "),
+        suggestions,
+    }
+}
+
+/// ghci keeps counting lines across inputs, find the offset from the snippet
+/// or assume a single line input is the spanned line
+fn map_span((start, end): RawSpan, snippet: Option<(usize, &str)>, source: &str) -> Option<Span> {
+    let lines: Vec<&str> = source.lines().collect();
+    let offset = match snippet {
+        Some((number, text)) => {
+            let row = lines
+                .iter()
+                .position(|line| line.trim_end() == text.trim_end())?;
+            number.checked_sub(row)?
+        }
+        None if lines.len() == 1 => start.0,
+        None => return None,
+    };
+    // offset is the ghci number of the first input line
+    let row = |line: usize| (line + 1).checked_sub(offset);
+    Some(Span {
+        start: (row(start.0)?, start.1),
+        end: (row(end.0)?, end.1),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bullet_suggestions() {
+        let err = "<interactive>:1:1: error:\n    • Variable not in scope: lenght :: [a] -> t\n    • Perhaps you meant ‘length’ (imported from Prelude)\n";
+        let (diagnostics, rest) = parse(err, "lenght [1]");
+        assert_eq!(rest, "");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "Variable not in scope: lenght :: [a] -> t"
+        );
+        assert_eq!(
+            diagnostics[0].suggestions,
+            vec!["Perhaps you meant ‘length’ (imported from Prelude)"]
+        );
+    }
+
+    #[test]
+    fn ghc_9_4_suggested_fix_line() {
+        let err = "<interactive>:1:1: error: [GHC-88464]\n    Variable not in scope: lenght :: [a0] -> t\n    Suggested fix:\n      Perhaps use ‘length’ (imported from Prelude)\n";
+        let (diagnostics, _) = parse(err, "lenght [1]");
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(
+            diagnostic.message,
+            "Variable not in scope: lenght :: [a0] -> t [GHC-88464]"
+        );
+        assert_eq!(
+            diagnostic.suggestions,
+            vec!["Perhaps use ‘length’ (imported from Prelude)"]
+        );
+        assert_eq!(
+            diagnostic.span,
+            Some(Span {
+                start: (1, 1),
+                end: (1, 1)
+            })
+        );
+    }
+
+    #[test]
+    fn ghc_9_4_suggested_fix_on_one_line() {
+        let err = "<interactive>:1:5: error: [GHC-58481]\n    parse error on input ‘->’\n    Suggested fix: Perhaps you intended to use LambdaCase\n";
+        let (diagnostics, _) = parse(err, "\\case -> 1");
+        assert_eq!(
+            diagnostics[0].suggestions,
+            vec!["Perhaps you intended to use LambdaCase"]
+        );
+        assert_eq!(
+            diagnostics[0].message,
+            "parse error on input ‘->’ [GHC-58481]"
+        );
+    }
+
+    #[test]
+    fn suggested_fixes_list() {
+        let err = "<interactive>:1:1: error: [GHC-88464]\n    Variable not in scope: fo\n    Suggested fixes:\n      • Perhaps use ‘foo’ (line 1)\n      • Use ‘fold’ instead\n";
+        let (diagnostics, _) = parse(err, "fo");
+        assert_eq!(
+            diagnostics[0].message,
+            "Variable not in scope: fo [GHC-88464]"
+        );
+        assert_eq!(
+            diagnostics[0].suggestions,
+            vec!["Perhaps use ‘foo’ (line 1)", "Use ‘fold’ instead"]
+        );
+    }
+
+    #[test]
+    fn warnings_keep_their_flag() {
+        let err = "<interactive>:1:1: warning: [-Wtype-defaults]\n    • Defaulting the following constraint to type ‘Integer’\n";
+        let (diagnostics, _) = parse(err, "1");
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(
+            diagnostics[0].message,
+            "Defaulting the following constraint to type ‘Integer’ [-Wtype-defaults]"
+        );
+    }
+}
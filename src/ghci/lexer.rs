@@ -0,0 +1,224 @@
+use crate::token::{Class, Cursor, Token};
+
+/// Haskell 2010 keywords, `proc` `rec` `mdo` need extensions and are plain names otherwise
+pub const KEYWORDS: &[&str] = &[
+    "case", "class", "data", "default", "deriving", "do", "else", "foreign", "if", "import", "in",
+    "infix", "infixl", "infixr", "instance", "let", "module", "newtype", "of", "then", "type",
+    "where",
+];
+
+fn is_symbol(c: char) -> bool {
+    "!#$%&*+./<=>?@\\^|-~:".contains(c)
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\''
+}
+
+/// Splits haskell source into tokens, the tokens cover the whole input
+pub fn lex(src: &str) -> Vec<Token> {
+    let mut cursor = Cursor::new(src);
+    let mut tokens = vec![];
+    while let Some(c) = cursor.peek() {
+        let start = cursor.pos();
+        let token = if c.is_whitespace() {
+            cursor.eat_while(char::is_whitespace);
+            cursor.token(Class::Whitespace, start)
+        } else if cursor.starts_with("{-") {
+            block_comment(&mut cursor)
+        } else if is_line_comment(&cursor) {
+            cursor.eat_while(|c| c != '\n');
+            cursor.token(Class::Comment, start)
+        } else if c == '"' {
+            string(&mut cursor)
+        } else if c == '\'' {
+            char_literal(&mut cursor)
+        } else if c.is_uppercase() {
+            qualified_name(&mut cursor, &mut tokens)
+        } else if c.is_alphabetic() || c == '_' {
+            cursor.eat_while(is_ident_char);
+            let class = if KEYWORDS.contains(&&src[start..cursor.pos()]) {
+                Class::Keyword
+            } else {
+                Class::Identifier
+            };
+            cursor.token(class, start)
+        } else if c.is_ascii_digit() {
+            number(&mut cursor)
+        } else if "([{".contains(c) {
+            cursor.bump();
+            cursor.token(Class::OpenBracket, start)
+        } else if ")]}".contains(c) {
+            cursor.bump();
+            cursor.token(Class::CloseBracket, start)
+        } else if is_symbol(c) {
+            cursor.eat_while(is_symbol);
+            cursor.token(Class::Operator, start)
+        } else {
+            cursor.bump();
+            cursor.token(Class::Punctuation, start)
+        };
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// `Maybe` `Data.Map.insert` `Map.!`
+/// the module qualifier is a type token followed by the name token
+fn qualified_name(cursor: &mut Cursor, tokens: &mut Vec<Token>) -> Token {
+    let start = cursor.pos();
+    loop {
+        // on an uppercase part
+        cursor.eat_while(is_ident_char);
+        if cursor.peek() != Some('.') {
+            return cursor.token(Class::Type, start);
+        }
+        let class = match cursor.peek_nth(1) {
+            Some(c) if c.is_uppercase() => {
+                cursor.skip(1);
+                continue;
+            }
+            Some(c) if c.is_lowercase() || c == '_' => Class::Identifier,
+            // `Map.!` but not `Just . f`
+            Some(c) if is_symbol(c) && c != '.' => Class::Operator,
+            _ => return cursor.token(Class::Type, start),
+        };
+        cursor.skip(1);
+        tokens.push(cursor.token(Class::Type, start));
+        let name_start = cursor.pos();
+        if class == Class::Operator {
+            cursor.eat_while(is_symbol);
+        } else {
+            cursor.eat_while(is_ident_char);
+        }
+        return cursor.token(class, name_start);
+    }
+}
+
+/// `42` `0x2A` `1_000` `4.2e-1`, `1..10` is a range not a float
+fn number(cursor: &mut Cursor) -> Token {
+    let start = cursor.pos();
+    cursor.eat_while(|c| c.is_ascii_alphanumeric() || c == '_');
+    if cursor.peek() == Some('.') && matches!(cursor.peek_nth(1), Some(c) if c.is_ascii_digit()) {
+        cursor.skip(1);
+        cursor.eat_while(|c| c.is_ascii_alphanumeric() || c == '_');
+    }
+    // exponent sign `4.2e-1`
+    let number = cursor.since(start);
+    let exponent = !number.starts_with("0x")
+        && !number.starts_with("0X")
+        && (number.ends_with('e') || number.ends_with('E'))
+        && matches!(cursor.peek(), Some('-') | Some('+'))
+        && matches!(cursor.peek_nth(1), Some(c) if c.is_ascii_digit());
+    if exponent {
+        cursor.skip(1);
+        cursor.eat_while(|c| c.is_ascii_digit());
+    }
+    cursor.token(Class::Number, start)
+}
+
+/// `--` starts a comment but `-->` is an operator
+fn is_line_comment(cursor: &Cursor) -> bool {
+    let rest = cursor.rest();
+    if !rest.starts_with("--") {
+        return false;
+    }
+    match rest.trim_start_matches('-').chars().next() {
+        Some(c) => !is_symbol(c),
+        None => true,
+    }
+}
+
+/// `{- -}` comments nest
+fn block_comment(cursor: &mut Cursor) -> Token {
+    let start = cursor.pos();
+    let mut depth = 0;
+    while !cursor.is_eof() {
+        if cursor.starts_with("{-") {
+            depth += 1;
+            cursor.skip(2);
+        } else if cursor.starts_with("-}") {
+            depth -= 1;
+            cursor.skip(2);
+            if depth == 0 {
+                return cursor.token(Class::Comment, start);
+            }
+        } else {
+            cursor.bump();
+        }
+    }
+    Token {
+        closed: false,
+        ..cursor.token(Class::Comment, start)
+    }
+}
+
+fn string(cursor: &mut Cursor) -> Token {
+    let start = cursor.pos();
+    cursor.bump();
+    while let Some(c) = cursor.bump() {
+        match c {
+            '"' => return cursor.token(Class::String, start),
+            // escapes and string gaps `\   \`
+            '\\' => {
+                if matches!(cursor.peek(), Some(c) if c.is_whitespace()) {
+                    cursor.eat_while(char::is_whitespace);
+                    if cursor.peek() == Some('\\') {
+                        cursor.bump();
+                    }
+                } else {
+                    cursor.bump();
+                }
+            }
+            '\n' => break,
+            _ => (),
+        }
+    }
+    Token {
+        closed: false,
+        ..cursor.token(Class::String, start)
+    }
+}
+
+/// `'a'` `'\n'`, a lone `'` is a template haskell quote
+fn char_literal(cursor: &mut Cursor) -> Token {
+    let start = cursor.pos();
+    cursor.bump();
+    match (cursor.peek(), cursor.peek_nth(1)) {
+        (Some('\\'), _) => {
+            cursor.bump();
+            cursor.bump();
+            cursor.eat_while(|c| c != '\'' && c != '\n');
+            if cursor.peek() == Some('\'') {
+                cursor.bump();
+                return cursor.token(Class::Char, start);
+            }
+            Token {
+                closed: false,
+                ..cursor.token(Class::Char, start)
+            }
+        }
+        (Some(c), Some('\'')) if c != '\'' => {
+            cursor.bump();
+            cursor.bump();
+            cursor.token(Class::Char, start)
+        }
+        _ => cursor.token(Class::Punctuation, start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_keywords_are_names() {
+        let src = "rec proc mdo let";
+        let keywords: Vec<&str> = lex(src)
+            .iter()
+            .filter(|token| token.class == Class::Keyword)
+            .map(|token| token.text(src))
+            .collect();
+        assert_eq!(keywords, ["let"]);
+    }
+}
@@ -1,111 +1,490 @@
-use rustyline::Color;
-use std::io::{prelude::*, BufReader};
-use std::process::Stdio;
+use rustyline::validate::ValidationResult;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 
+use crate::highlight;
+use crate::query::{self, Backend, Evaluation, Querier, Request};
+use crate::theme::Role;
+use crate::token::{self, Class, Token};
+use crate::utils;
+
+mod lexer;
+
 const PRELUDE_MARK: &[u8] = b"gjs> ";
+/// The end of the `....` prompt of an unfinished statement
+const CONTINUATION_MARK: &[u8] = b".. ";
+
+pub fn gjs(rx_in: mpsc::Receiver<Request>, tx_out: mpsc::Sender<Evaluation>) {
+    query::serve::<Gjs>(rx_in, tx_out)
+}
+
+pub struct Gjs;
+
+impl Backend for Gjs {
+    const COMMAND: &'static str = "gjs";
+    const MARKS: &'static [&'static [u8]] = &[PRELUDE_MARK];
+
+    const CONTINUATION: Option<&'static [u8]> = Some(CONTINUATION_MARK);
+
+    fn tokens(src: &str) -> Vec<Token> {
+        lexer::lex(src)
+    }
+
+    fn sync(mark: &str) -> String {
+        format!("printerr(\"{}\")\n", mark)
+    }
+
+    /// newlines are kept, they can end statements
+    fn prepare(source: &str, number: usize) -> String {
+        keep_result(source, number) + "\n"
+    }
 
-pub fn gjs(rx_in: mpsc::Receiver<String>, tx_out: mpsc::Sender<String>) {
-    let mut process = std::process::Command::new("gjs")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .unwrap();
-    let pid = process.id();
-    let mut stdout = BufReader::new(process.stdout.as_mut().unwrap());
-
-    let mut out = vec![];
-    let mut buf = [0; 512];
-    //read welcome message
-    let mut read = |out: &mut Vec<u8>, mut buf: &mut [u8]| loop {
-        let n = stdout.read(&mut buf).unwrap();
-        out.extend(buf.iter().take(n));
-        if out.ends_with(PRELUDE_MARK) {
-            break;
+    /// gjs echoes the input before the answer, the echo can differ once the terminal rewrote it
+    fn answer<'o>(out: &'o str, inp: &str) -> Cow<'o, str> {
+        match strip_echo(out, inp) {
+            Some(answer) => answer.into(),
+            None => out.into(),
         }
+    }
+
+    fn highlight_output(out: &str) -> String {
+        highlight_output(out)
+    }
+
+    /// uncaught exceptions are reported on stderr, next to what `printerr` and `log` print
+    fn failed(_out: &str, err: &str) -> bool {
+        err.lines().any(is_error)
+    }
+}
+
+/// `typein:1:1 uncaught exception: ReferenceError: x is not defined`
+/// `typein:1:4 SyntaxError: ..` `JS ERROR: TypeError: ..`
+fn is_error(line: &str) -> bool {
+    line.contains("uncaught exception")
+        || line.contains("JS ERROR")
+        || (line.starts_with("typein:") && line.contains("Error"))
+}
+
+/// Keywords starting a statement, it has no value to keep
+const STATEMENTS: &[&str] = &[
+    "let", "const", "var", "function", "async", "class", "if", "for", "while", "do", "switch",
+    "try", "throw", "return", "import", "export", "break", "continue",
+];
+
+/// An expression is assigned to `globalThis._3` on the way, gjs has no `it`
+fn keep_result(inp: &str, number: usize) -> String {
+    let tokens = lexer::lex(inp);
+    let significant: Vec<&Token> = tokens.iter().filter(|token| !token.is_trivia()).collect();
+    // `1 + 1;` is still an expression
+    let end = significant
+        .iter()
+        .rposition(|token| token.text(inp) != ";")
+        .map(|last| last + 1)
+        .unwrap_or(0);
+    let expression = &significant[..end];
+    let first = match expression.first() {
+        Some(first) => first.text(inp),
+        None => return inp.to_owned(),
     };
+    let is_statement = first == "{"
+        || STATEMENTS.contains(&first)
+        || expression.iter().any(|token| token.text(inp) == ";");
+    if is_statement {
+        return inp.to_owned();
+    }
+    // a line comment at the end would swallow the `)`
+    let expression = &inp[..expression[end - 1].end];
+    format!("globalThis.{} = ({}\n)", Gjs::cell_name(number), expression)
+}
 
-    read(&mut out, &mut buf);
+/// The output without the echo of each line of `inp`
+/// the lines after the first are echoed after a `....` prompt, or after `gjs>`
+/// when the lines before were a statement on their own, its answer is kept then
+fn strip_echo(out: &str, inp: &str) -> Option<String> {
+    let mut answer = String::new();
+    let mut rest = out;
+    for (idx, line) in inp.lines().enumerate() {
+        let echo = format!("{}\n", line);
+        if idx > 0 {
+            let prompt = String::from_utf8_lossy(PRELUDE_MARK);
+            let (at, _) = rest.match_indices(&echo).find(|(at, _)| {
+                rest[..*at].ends_with(prompt.as_ref()) || rest[..*at].ends_with(".. ")
+            })?;
+            match rest[..at].strip_suffix(prompt.as_ref()) {
+                Some(before) => answer.push_str(before),
+                None => answer.push_str(rest[..at].trim_end_matches(' ').trim_end_matches('.')),
+            }
+            rest = &rest[at..];
+        }
+        rest = rest.strip_prefix(&echo)?;
+    }
+    answer.push_str(rest);
+    Some(answer)
+}
 
-    let (tx_err, rx_err) = mpsc::channel();
-    let mut stderr = process.stderr.take();
-    std::thread::spawn(move || {
-        let mut err = [0; 500];
-        loop {
-            let n = stderr.as_mut().unwrap().read(&mut err).unwrap();
-            let _ = tx_err.send(String::from_utf8(err[..n].to_vec()).unwrap());
+/// Asks for more lines while the input is unfinished
+/// - inside a `/* */` comment or a template literal
+/// - inside brackets, including the `${ }` of template literals
+/// - after a binary operator, `=` or `=>`
+/// - before the body of a statement: `if (x)` `function f()` `class A` `else`
+pub fn validate(input: &str) -> ValidationResult {
+    let tokens = lexer::lex(input);
+    // comments and template literals go on over the next line
+    let continues =
+        |token: &Token| token.class == Class::Comment || lexer::is_template_chunk(input, token);
+    if let Some(result) = token::check_open(input, &tokens, continues) {
+        return result;
+    }
+    if awaits_body(input, &tokens) {
+        return ValidationResult::Incomplete;
+    }
+
+    match token::last_significant(&tokens) {
+        Some(last)
+            if last.class == Class::Operator && !["++", "--"].contains(&last.text(input)) =>
+        {
+            ValidationResult::Incomplete
         }
+        _ => ValidationResult::Valid(None),
+    }
+}
+
+/// Keywords followed by a block
+const BLOCK_KEYWORDS: &[&str] = &["else", "try", "do", "finally", "class", "extends"];
+
+/// Keywords whose `( )` header is followed by the body
+const HEADER_KEYWORDS: &[&str] = &["if", "for", "while", "with", "switch", "catch", "function"];
+
+/// Does the input end where a statement body must follow, the brackets are balanced
+fn awaits_body(input: &str, tokens: &[Token]) -> bool {
+    let significant: Vec<&Token> = tokens.iter().filter(|token| !token.is_trivia()).collect();
+    let text = |idx: usize| significant[idx].text(input);
+    // `promise.catch(f)` `p?.finally`: keywords are property names after a dot
+    let is_keyword = |idx: usize, keywords: &[&str]| {
+        keywords.contains(&text(idx)) && !(idx >= 1 && [".", "?."].contains(&text(idx - 1)))
+    };
+    let last = match significant.len().checked_sub(1) {
+        Some(last) => last,
+        None => return false,
+    };
+    if is_keyword(last, BLOCK_KEYWORDS) {
+        return true;
+    }
+    // `class A`, `class A extends B`
+    if significant[last].class == Class::Identifier
+        && last >= 1
+        && ["class", "extends"].contains(&text(last - 1))
+    {
+        return true;
+    }
+    if text(last) != ")" {
+        return false;
+    }
+    let mut depth = 0;
+    let open = (0..=last).rev().find(|&idx| {
+        match significant[idx].class {
+            Class::CloseBracket => depth += 1,
+            Class::OpenBracket => depth -= 1,
+            _ => (),
+        }
+        depth == 0
     });
+    let open = match open {
+        Some(open) if open >= 1 => open,
+        _ => return false,
+    };
+    let keyword = open - 1;
+    if is_keyword(keyword, &["while"]) {
+        // `do { } while (x)` is finished
+        return !(open >= 2 && text(open - 2) == "}");
+    }
+    if is_keyword(keyword, HEADER_KEYWORDS) {
+        return true;
+    }
+    // `function f()`, `function* f()`
+    significant[keyword].class == Class::Identifier
+        && open >= 2
+        && ["function", "*"].contains(&text(open - 2))
+}
+
+/// Property accesses `imports.gi.Gtk` are completed as one word
+pub fn word_start(line: &str, pos: usize) -> usize {
+    utils::word_start(line, pos, |c| is_identifier_char(c) || c == '.')
+}
+
+/// Prints every property name of `receiver` and its prototype chain, one per line
+const PROPERTY_NAMES_QUERY: &str = "try { print((function (o) { \
+    const names = new Set(); \
+    for (; o !== null && o !== undefined; o = Object.getPrototypeOf(o)) \
+        Object.getOwnPropertyNames(o).forEach(n => names.add(n)); \
+    return [...names].join('\\n'); \
+})(RECEIVER)) } catch (e) {}";
+
+/// Complete properties after a `.`, `imports.gi.G` => `imports.gi.Gtk`
+pub fn complete(querier: &Querier, word: &str) -> Vec<String> {
+    let dot = match word.rfind('.') {
+        Some(dot) => dot,
+        None => return vec![],
+    };
+    let (receiver, prefix) = (&word[..dot], &word[dot + 1..]);
+    // only evaluate plain property paths, `f().x` would run `f`
+    if !receiver.split('.').all(is_identifier) {
+        return vec![];
+    }
+
+    let names = if receiver == "imports.gi" {
+        gi_namespaces()
+    } else {
+        match querier.query(&PROPERTY_NAMES_QUERY.replace("RECEIVER", receiver)) {
+            Some(out) => out.lines().map(|name| name.trim().to_owned()).collect(),
+            None => vec![],
+        }
+    };
+    names
+        .into_iter()
+        .filter(|name| is_identifier(name) && name.starts_with(prefix))
+        .map(|name| format!("{}.{}", receiver, name))
+        .collect()
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
 
-    ctrlc::set_handler(move || {
-        use nix::{
-            sys::signal::{kill, Signal},
-            unistd::Pid,
+fn is_identifier(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(is_identifier_char)
+}
+
+/// GI namespaces are loaded lazily so ask the installed typelibs instead
+/// `Gtk-3.0.typelib` => `Gtk`
+fn gi_namespaces() -> Vec<String> {
+    let mut dirs: Vec<PathBuf> = std::env::var("GI_TYPELIB_PATH")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    for lib in &["/usr/lib", "/usr/lib64", "/usr/local/lib"] {
+        dirs.push(Path::new(lib).join("girepository-1.0"));
+        // debian multiarch: /usr/lib/x86_64-linux-gnu/girepository-1.0
+        if let Ok(entries) = std::fs::read_dir(lib) {
+            dirs.extend(entries.filter_map(|e| Some(e.ok()?.path().join("girepository-1.0"))));
+        }
+    }
+
+    let mut namespaces = vec![];
+    for dir in dirs {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
         };
-        let _ = kill(Pid::from_raw(pid as i32), Some(Signal::SIGINT));
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if !name.ends_with(".typelib") {
+                continue;
+            }
+            if let Some(namespace) = name.split('-').next() {
+                namespaces.push(namespace.to_owned());
+            }
+        }
+    }
+    namespaces.sort();
+    namespaces.dedup();
+    namespaces
+}
+
+/// Globals gjs adds to javascript
+const GJS_GLOBALS: &[&str] = &[
+    "imports",
+    "print",
+    "printerr",
+    "log",
+    "logError",
+    "ARGV",
+    "pkg",
+    "globalThis",
+];
+
+pub fn highlight(line: &str, pos: usize) -> String {
+    let tokens = lexer::lex(line);
+    highlight::paint(line, &tokens, Some(pos), |token| style(line, token))
+}
+
+/// Printed values, object keys stand out
+pub fn highlight_output(out: &str) -> String {
+    if highlight::is_colored(out) {
+        return out.to_owned();
+    }
+    let tokens = lexer::lex(out);
+    let keys = highlight::keys(out, &tokens, ":");
+    highlight::paint(out, &tokens, None, |token| {
+        if keys.contains(&token.start) {
+            Some(Role::Field)
+        } else {
+            style(out, token)
+        }
     })
-    .expect("Error setting Ctrl-C handler");
-
-    loop {
-        out.clear();
-        let inp = match rx_in.recv() {
-            Ok(inp) => inp,
-            // program has ended
-            _ => break,
-        };
+}
+
+fn style(src: &str, token: &Token) -> Option<Role> {
+    let text = token.text(src);
+    let role = match token.class {
+        Class::Keyword => match text {
+            "const" | "let" | "var" | "function" | "class" | "async" | "static" | "extends" => {
+                Role::Declaration
+            }
+            "true" | "false" | "null" | "undefined" | "this" | "super" => Role::Constant,
+            _ => Role::Keyword,
+        },
+        Class::Identifier if GJS_GLOBALS.contains(&text) => Role::Builtin,
+        Class::Operator => match text {
+            "=>" => Role::Declaration,
+            "." | "?." => return None,
+            _ => Role::Operator,
+        },
+        // regex literals
+        Class::String if text.starts_with('/') => Role::Regex,
+        Class::String => Role::String,
+        Class::Number => Role::Number,
+        Class::Comment => Role::Comment,
+        _ => return None,
+    };
+    Some(role)
+}
 
-        process
-            .stdin
-            .as_mut()
-            .unwrap()
-            .write_all(inp.as_bytes())
-            .unwrap();
-
-        read(&mut out, &mut buf);
-
-        // remove gjs>
-        let out = String::from_utf8(out[..out.len() - 5].to_vec()).unwrap();
-        // remove the input from the output (for some reason its included)
-        let out = out.strip_prefix(&inp).unwrap();
-        let err: String = rx_err.try_iter().collect();
-        tx_out.send(out.to_owned() + &err).unwrap();
-    }
-}
-
-pub fn highlight(line: &str, _pos: usize) -> String {
-    // these chars can't be replaced \x1b[;3m0
-    line.replace("exception", &"exception".red())
-        .replace("datatype", &"datatype".green())
-        //sig + signature
-        .replace("sig", &"sig".green())
-        .replace("nature", &"nature".green())
-        //struct + structure
-        .replace("struct", &"struct".green())
-        .replace("const", &"const".rgb(194, 14, 234))
-        .replace("type", &"type".green())
-        .replace("raise", &"raise".red())
-        .replace("case", &"case".light_blue())
-        .replace("then", &"then".light_blue())
-        .replace("else", &"else".light_blue())
-        .replace("let", &"let".rgb(194, 14, 234))
-        .replace("val", &"val".rgb(194, 14, 234))
-        .replace("end", &"end".green())
-        .replace("fun", &"fun".green())
-        .replace("of", &"of".light_blue())
-        .replace("if", &"if".light_blue())
-        .replace("|", &"|".light_blue())
-        .replace("(", &"(".yellow())
-        .replace("*", &"*".yellow())
-        .replace(")", &")".yellow())
-        .replace(":", &":".yellow())
-        .replace("-", &"-".yellow())
-        .replace("~", &"~".yellow())
-        .replace("+", &"+".yellow())
-        .replace(">", &">".yellow())
-        .replace("<", &"<".yellow())
-        .replace("/", &"/".yellow())
-        .replace("=", &"=".yellow())
-        .replace(",", &",".yellow())
-        .replace(".", &".".red())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::{is_incomplete, is_valid};
+
+    #[test]
+    fn expressions_are_kept_as_results() {
+        assert_eq!(keep_result("1 + 1", 3), "globalThis._3 = (1 + 1\n)");
+        assert_eq!(keep_result("f(x); // call", 3), "globalThis._3 = (f(x)\n)");
+        assert_eq!(keep_result("let x = 1", 3), "let x = 1");
+        assert_eq!(keep_result("f(); g()", 3), "f(); g()");
+        assert_eq!(keep_result("{ x }", 3), "{ x }");
+        assert_eq!(keep_result("", 3), "");
+    }
+
+    #[test]
+    fn statement_headers_wait_for_a_body() {
+        assert!(is_incomplete(validate("if (x)")));
+        assert!(is_incomplete(validate("if (f(x))")));
+        assert!(is_incomplete(validate("for (const a of b)")));
+        assert!(is_incomplete(validate("while (true)")));
+        assert!(is_incomplete(validate("switch (x)")));
+        assert!(is_incomplete(validate("if (a) b;\nelse if (c)")));
+        assert!(is_incomplete(validate("try { f() } catch (e)")));
+    }
+
+    #[test]
+    fn signatures_and_classes_wait_for_a_body() {
+        assert!(is_incomplete(validate("function f()")));
+        assert!(is_incomplete(validate("function f(a, b)")));
+        assert!(is_incomplete(validate("function* gen()")));
+        assert!(is_incomplete(validate("function ()")));
+        assert!(is_incomplete(validate("class A")));
+        assert!(is_incomplete(validate("class A extends B")));
+    }
+
+    #[test]
+    fn block_keywords_wait_for_a_block() {
+        assert!(is_incomplete(validate("if (a) b;\nelse")));
+        assert!(is_incomplete(validate("try")));
+        assert!(is_incomplete(validate("do")));
+        assert!(is_incomplete(validate("try { f() } finally")));
+    }
+
+    #[test]
+    fn finished_statements_are_valid() {
+        assert!(is_valid(validate("if (x) y()")));
+        assert!(is_valid(validate("f(x)")));
+        assert!(is_valid(validate("function f() {}")));
+        assert!(is_valid(validate("class A {}")));
+        assert!(is_valid(validate("do { x++ } while (x < 3)")));
+        assert!(is_valid(validate("let a = (1 + 2)")));
+    }
+
+    #[test]
+    fn unfinished_input() {
+        assert!(is_incomplete(validate("f(1,")));
+        assert!(is_incomplete(validate("/* comment")));
+        assert!(is_incomplete(validate("`a ${b")));
+        assert!(is_incomplete(validate("1 +")));
+        assert!(is_incomplete(validate("x =>")));
+    }
+
+    #[test]
+    fn errors_on_stderr() {
+        assert!(is_error(
+            "typein:1:1 uncaught exception: ReferenceError: x is not defined"
+        ));
+        assert!(is_error(
+            "(gjs:123): Gjs-WARNING **: JS ERROR: TypeError: a is null"
+        ));
+        assert!(!is_error("Gjs-Message: 12:00:00.000: JS LOG: hello"));
+        assert!(!is_error("printed with printerr"));
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(matches!(validate("f(]"), ValidationResult::Invalid(_)));
+        assert!(matches!(validate("'abc"), ValidationResult::Invalid(_)));
+    }
+
+    #[test]
+    fn keywords_after_a_dot_are_properties() {
+        assert!(is_valid(validate("promise.catch(f)")));
+        assert!(is_valid(validate("arr.with(0, 1)")));
+        assert!(is_valid(validate("p.finally")));
+        assert!(is_valid(validate("p?.finally")));
+        assert!(is_valid(validate("a.while(x)")));
+        assert!(is_valid(validate("x.do")));
+    }
+
+    #[test]
+    fn lines_are_sent_as_typed() {
+        let source = "function f() {\n  const x = 1\n  return x\n}";
+        assert_eq!(Gjs::prepare(source, 1), format!("{}\n", source));
+        assert_eq!(
+            Gjs::prepare("f(1,\n  2)", 2),
+            "globalThis._2 = (f(1,\n  2)\n)\n"
+        );
+    }
+
+    #[test]
+    fn echoes_are_stripped() {
+        assert_eq!(strip_echo("1 + 1\n2\n", "1 + 1\n").as_deref(), Some("2\n"));
+        assert_eq!(
+            strip_echo("f(1,\n.... 2)\n3\n", "f(1,\n2)\n").as_deref(),
+            Some("3\n")
+        );
+        // each line is a statement of its own
+        assert_eq!(
+            strip_echo("1\n1\ngjs> 2\n2\n", "1\n2\n").as_deref(),
+            Some("1\n2\n")
+        );
+        assert_eq!(strip_echo("other\n", "1 + 1\n"), None);
+    }
+
+    #[test]
+    fn highlighting_keeps_the_text() {
+        for line in [
+            "const re = /[(]/g; // (",
+            "`a ${b + `c ${d}`} e` + \"(\\\"\"",
+            "class A extends B { #x = 1; get y() { return this.#x } }",
+            "let ünïcode = \"ü\"; ünïcode.length",
+            "[1, 2].map((x) => x ** 2)\n  .filter(Boolean)",
+        ] {
+            for pos in [0, line.len() / 2, line.len()] {
+                if line.is_char_boundary(pos) {
+                    assert_eq!(highlight::visible(&highlight(line, pos)), line);
+                }
+            }
+            assert_eq!(highlight::visible(&highlight_output(line)), line);
+        }
+    }
 }
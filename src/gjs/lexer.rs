@@ -0,0 +1,342 @@
+use crate::token::{Class, Cursor, Token};
+
+pub const KEYWORDS: &[&str] = &[
+    "async",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "import",
+    "in",
+    "instanceof",
+    "let",
+    "new",
+    "null",
+    "of",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+/// Keywords after which a `/` starts a regex, not a division
+const REGEX_AFTER: &[&str] = &[
+    "return",
+    "typeof",
+    "instanceof",
+    "in",
+    "of",
+    "new",
+    "delete",
+    "void",
+    "throw",
+    "case",
+    "do",
+    "else",
+    "yield",
+    "await",
+];
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == '$'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+fn is_operator(c: char) -> bool {
+    "+-*/%=&|^!~<>?:.".contains(c)
+}
+
+/// Splits javascript source into tokens, the tokens cover the whole input
+/// template literals are split around their `${ }` which are bracket tokens
+pub fn lex(src: &str) -> Vec<Token> {
+    let mut cursor = Cursor::new(src);
+    let mut tokens: Vec<Token> = vec![];
+    // for every open `{`: does it belong to a template `${`
+    let mut braces = vec![];
+    while let Some(c) = cursor.peek() {
+        let start = cursor.pos();
+        let token = if c.is_whitespace() {
+            cursor.eat_while(char::is_whitespace);
+            cursor.token(Class::Whitespace, start)
+        } else if cursor.starts_with("//") {
+            cursor.eat_while(|c| c != '\n');
+            cursor.token(Class::Comment, start)
+        } else if cursor.starts_with("/*") {
+            block_comment(&mut cursor)
+        } else if c == '"' || c == '\'' {
+            string(&mut cursor, c)
+        } else if c == '`' {
+            cursor.bump();
+            template(&mut cursor, start, &mut braces)
+        } else if c == '}' && braces.last() == Some(&true) {
+            // back in the template after `${ }`
+            braces.pop();
+            cursor.bump();
+            tokens.push(cursor.token(Class::CloseBracket, start));
+            template(&mut cursor, start + 1, &mut braces)
+        } else if c == '/' && regex_allowed(src, &tokens) {
+            regex(&mut cursor)
+        } else if is_ident_start(c) {
+            cursor.eat_while(is_ident_char);
+            let class = if KEYWORDS.contains(&&src[start..cursor.pos()]) {
+                Class::Keyword
+            } else {
+                Class::Identifier
+            };
+            cursor.token(class, start)
+        } else if c.is_ascii_digit()
+            || (c == '.' && matches!(cursor.peek_nth(1), Some(d) if d.is_ascii_digit()))
+        {
+            cursor.eat_while(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_');
+            cursor.token(Class::Number, start)
+        } else if "([{".contains(c) {
+            if c == '{' {
+                braces.push(false);
+            }
+            cursor.bump();
+            cursor.token(Class::OpenBracket, start)
+        } else if ")]}".contains(c) {
+            if c == '}' {
+                braces.pop();
+            }
+            cursor.bump();
+            cursor.token(Class::CloseBracket, start)
+        } else if is_operator(c) {
+            cursor.eat_while(is_operator);
+            cursor.token(Class::Operator, start)
+        } else {
+            cursor.bump();
+            cursor.token(Class::Punctuation, start)
+        };
+        // nothing may follow the `}` of an interpolation, the template is still open though
+        if token.start != token.end || !token.closed {
+            tokens.push(token);
+        }
+        if let Some(open) = take_interpolation(&mut tokens, src) {
+            tokens.push(open);
+        }
+    }
+    tokens
+}
+
+/// A template chunk ending with `${` is split into the string and an open bracket
+fn take_interpolation(tokens: &mut Vec<Token>, src: &str) -> Option<Token> {
+    let last = tokens.last_mut()?;
+    if !is_template_chunk(src, last) || !last.closed || !last.text(src).ends_with("${") {
+        return None;
+    }
+    last.end -= 2;
+    let open = Token {
+        class: Class::OpenBracket,
+        start: last.end,
+        end: last.end + 2,
+        closed: true,
+    };
+    if last.start == last.end {
+        tokens.pop();
+    }
+    Some(open)
+}
+
+/// Template chunks start at the `` ` `` or right after the `}` of an interpolation
+pub fn is_template_chunk(src: &str, token: &Token) -> bool {
+    token.class == Class::String
+        && (token.text(src).starts_with('`') || src[..token.start].ends_with('}'))
+}
+
+/// A `/` is a regex at the start of an expression
+fn regex_allowed(src: &str, tokens: &[Token]) -> bool {
+    let last = match tokens.iter().rev().find(|token| !token.is_trivia()) {
+        Some(last) => last,
+        None => return true,
+    };
+    match last.class {
+        Class::Identifier | Class::Number | Class::String | Class::CloseBracket => false,
+        Class::Keyword => REGEX_AFTER.contains(&last.text(src)),
+        _ => true,
+    }
+}
+
+fn block_comment(cursor: &mut Cursor) -> Token {
+    let start = cursor.pos();
+    cursor.skip(2);
+    while !cursor.is_eof() {
+        if cursor.starts_with("*/") {
+            cursor.skip(2);
+            return cursor.token(Class::Comment, start);
+        }
+        cursor.bump();
+    }
+    Token {
+        closed: false,
+        ..cursor.token(Class::Comment, start)
+    }
+}
+
+/// `'...'` and `"..."`, a `\` at the end of a line continues them
+fn string(cursor: &mut Cursor, quote: char) -> Token {
+    let start = cursor.pos();
+    cursor.bump();
+    while let Some(c) = cursor.bump() {
+        match c {
+            '\\' => {
+                cursor.bump();
+            }
+            '\n' => break,
+            c if c == quote => return cursor.token(Class::String, start),
+            _ => (),
+        }
+    }
+    Token {
+        closed: false,
+        ..cursor.token(Class::String, start)
+    }
+}
+
+/// Template chunk until the closing `` ` `` or a `${`
+fn template(cursor: &mut Cursor, start: usize, braces: &mut Vec<bool>) -> Token {
+    while let Some(c) = cursor.bump() {
+        match c {
+            '\\' => {
+                cursor.bump();
+            }
+            '`' => return cursor.token(Class::String, start),
+            '$' if cursor.peek() == Some('{') => {
+                cursor.bump();
+                braces.push(true);
+                return cursor.token(Class::String, start);
+            }
+            _ => (),
+        }
+    }
+    Token {
+        closed: false,
+        ..cursor.token(Class::String, start)
+    }
+}
+
+/// `/ab+c/gi`, a `/` inside a `[ ]` class doesn't end it
+fn regex(cursor: &mut Cursor) -> Token {
+    let start = cursor.pos();
+    cursor.bump();
+    let mut in_class = false;
+    while let Some(c) = cursor.bump() {
+        match c {
+            '\\' => {
+                cursor.bump();
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => {
+                cursor.eat_while(|c| c.is_ascii_alphabetic());
+                return cursor.token(Class::String, start);
+            }
+            '\n' => break,
+            _ => (),
+        }
+    }
+    Token {
+        closed: false,
+        ..cursor.token(Class::String, start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tokens that aren't whitespace
+    fn tokens(src: &str) -> Vec<(Class, &str)> {
+        lex(src)
+            .into_iter()
+            .filter(|token| token.class != Class::Whitespace)
+            .map(|token| (token.class, token.text(src)))
+            .collect()
+    }
+
+    #[test]
+    fn template_literals_split_around_substitutions() {
+        let src = "`a ${ {b: `c ${d}`}.b } e`";
+        let classes: Vec<Class> = tokens(src).into_iter().map(|(class, _)| class).collect();
+        assert_eq!(classes.first(), Some(&Class::String));
+        assert_eq!(classes.last(), Some(&Class::String));
+        assert!(lex(src).iter().all(|token| token.closed));
+        assert_eq!(tokens(src)[1], (Class::OpenBracket, "${"));
+    }
+
+    #[test]
+    fn unfinished_template_literal() {
+        let src = "`a ${b} c";
+        let last = *lex(src).last().unwrap();
+        assert!(!last.closed);
+        assert!(is_template_chunk(src, &last));
+    }
+
+    #[test]
+    fn regex_or_division() {
+        assert_eq!(tokens("a / b / c")[1], (Class::Operator, "/"));
+        assert_eq!(tokens("x = /[)/]+/g")[2], (Class::String, "/[)/]+/g"));
+        assert_eq!(tokens("return /a/")[1], (Class::String, "/a/"));
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            tokens("a /* ( */ b // )"),
+            vec![
+                (Class::Identifier, "a"),
+                (Class::Comment, "/* ( */"),
+                (Class::Identifier, "b"),
+                (Class::Comment, "// )"),
+            ]
+        );
+        assert!(!lex("/* a")[0].closed);
+    }
+
+    #[test]
+    fn keywords_and_operators() {
+        assert_eq!(
+            tokens("const f = async (x) => x"),
+            vec![
+                (Class::Keyword, "const"),
+                (Class::Identifier, "f"),
+                (Class::Operator, "="),
+                (Class::Keyword, "async"),
+                (Class::OpenBracket, "("),
+                (Class::Identifier, "x"),
+                (Class::CloseBracket, ")"),
+                (Class::Operator, "=>"),
+                (Class::Identifier, "x"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use crate::theme::{Role, THEME};
+use crate::token::{self, Class, Token};
+
+/// Wraps each token in the theme style of its role, the visible text is the source unchanged
+/// brackets get their rainbow color whatever `style` says,
+/// the one at (or right before) the cursor is emphasized with its partner
+pub fn paint(
+    src: &str,
+    tokens: &[Token],
+    cursor: Option<usize>,
+    style: impl Fn(&Token) -> Option<Role>,
+) -> String {
+    let brackets = pair_brackets(src, tokens);
+    let emphasized = match cursor {
+        Some(pos) => cursor_pair(tokens, &brackets, pos),
+        None => [None, None],
+    };
+    let mut painted = String::with_capacity(src.len() * 2);
+    for (idx, token) in tokens.iter().enumerate() {
+        let text = token.text(src);
+        let sgr = match brackets[idx] {
+            Some(Bracket::Paired(depth, _)) => THEME.rainbow(depth),
+            Some(Bracket::Unmatched) => THEME.style(Role::Unmatched),
+            None => style(token).and_then(|role| THEME.style(role)),
+        };
+        let emphasis = THEME
+            .style(Role::CursorBracket)
+            .filter(|_| emphasized.contains(&Some(idx)));
+        match (sgr, emphasis) {
+            (Some(sgr), Some(emphasis)) => {
+                painted.push_str(&paint_str(text, &format!("{};{}", sgr, emphasis)))
+            }
+            (Some(sgr), None) | (None, Some(sgr)) => painted.push_str(&paint_str(text, sgr)),
+            (None, None) => painted.push_str(text),
+        }
+    }
+    painted
+}
+
+pub fn paint_str(text: &str, sgr: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", sgr, text)
+}
+
+/// The text of painted output without its escape sequences
+#[cfg(test)]
+pub fn visible(painted: &str) -> String {
+    let mut text = String::with_capacity(painted.len());
+    let mut rest = painted;
+    while let Some(esc) = rest.find('\x1b') {
+        text.push_str(&rest[..esc]);
+        let end = rest[esc..].find('m').map_or(rest.len(), |m| esc + m + 1);
+        rest = &rest[end..];
+    }
+    text.push_str(rest);
+    text
+}
+
+/// Output the child colored itself is printed as is, its escapes would be lexed as text
+pub fn is_colored(out: &str) -> bool {
+    out.contains('\x1b')
+}
+
+/// Starts of the identifiers followed by `sep`, record fields `x = 1` or object keys `x: 1`
+pub fn keys(src: &str, tokens: &[Token], sep: &str) -> Vec<usize> {
+    let significant: Vec<&Token> = tokens.iter().filter(|token| !token.is_trivia()).collect();
+    significant
+        .windows(2)
+        .filter(|pair| pair[0].class == Class::Identifier && pair[1].text(src) == sep)
+        .map(|pair| pair[0].start)
+        .collect()
+}
+
+/// Is there a bracket char at or right before `pos`, the line needs repainting then
+pub fn near_bracket(line: &str, pos: usize) -> bool {
+    let is_bracket = |c: char| "()[]{}".contains(c);
+    line[pos..].starts_with(is_bracket) || line[..pos].ends_with(is_bracket)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Bracket {
+    /// nesting depth and the index of the other bracket of the pair
+    /// brackets still open at the end keep their color, the input is just incomplete
+    Paired(usize, Option<usize>),
+    /// a closing bracket with no opener or the wrong one
+    Unmatched,
+}
+
+/// Pairs the bracket tokens by depth, None for the other tokens
+fn pair_brackets(src: &str, tokens: &[Token]) -> Vec<Option<Bracket>> {
+    let mut brackets = vec![None; tokens.len()];
+    let mut stack: Vec<usize> = vec![];
+    for (idx, token) in tokens.iter().enumerate() {
+        match token.class {
+            Class::OpenBracket => {
+                brackets[idx] = Some(Bracket::Paired(stack.len(), None));
+                stack.push(idx);
+            }
+            Class::CloseBracket => {
+                let expected = stack
+                    .last()
+                    .and_then(|&open| tokens[open].text(src).chars().last())
+                    .and_then(token::matching_bracket);
+                match stack.last() {
+                    Some(&open) if expected == token.text(src).chars().next() => {
+                        stack.pop();
+                        brackets[open] = Some(Bracket::Paired(stack.len(), Some(idx)));
+                        brackets[idx] = Some(Bracket::Paired(stack.len(), Some(open)));
+                    }
+                    _ => brackets[idx] = Some(Bracket::Unmatched),
+                }
+            }
+            _ => (),
+        }
+    }
+    brackets
+}
+
+/// The bracket token under the cursor, or else right before it, and its partner
+fn cursor_pair(tokens: &[Token], brackets: &[Option<Bracket>], pos: usize) -> [Option<usize>; 2] {
+    let at = tokens
+        .iter()
+        .position(|token| token.start <= pos && pos < token.end)
+        .filter(|&idx| brackets[idx].is_some());
+    let before = || {
+        tokens
+            .iter()
+            .position(|token| token.end == pos)
+            .filter(|&idx| brackets[idx].is_some())
+    };
+    match at.or_else(before) {
+        Some(idx) => match brackets[idx] {
+            Some(Bracket::Paired(_, Some(partner))) => [Some(idx), Some(partner)],
+            _ => [None, None],
+        },
+        None => [None, None],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One token per char, brackets are bracket tokens
+    fn tokens(src: &str) -> Vec<Token> {
+        src.char_indices()
+            .map(|(start, c)| Token {
+                class: match c {
+                    '(' | '[' | '{' => Class::OpenBracket,
+                    ')' | ']' | '}' => Class::CloseBracket,
+                    _ => Class::Identifier,
+                },
+                start,
+                end: start + c.len_utf8(),
+                closed: true,
+            })
+            .collect()
+    }
+
+    /// `(depth, partner)` of the paired brackets, `!` for unmatched ones, `.` for the rest
+    fn pairs(src: &str) -> Vec<String> {
+        pair_brackets(src, &tokens(src))
+            .iter()
+            .map(|bracket| match bracket {
+                Some(Bracket::Paired(depth, Some(partner))) => format!("{}:{}", depth, partner),
+                Some(Bracket::Paired(depth, None)) => format!("{}:-", depth),
+                Some(Bracket::Unmatched) => "!".to_owned(),
+                None => ".".to_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn brackets_pair_by_depth() {
+        assert_eq!(pairs("(a[b])"), ["0:5", ".", "1:4", ".", "1:2", "0:0"]);
+        assert_eq!(pairs("{}()"), ["0:1", "0:0", "0:3", "0:2"]);
+    }
+
+    #[test]
+    fn open_and_unmatched_brackets() {
+        // still open at the end, the input is just incomplete
+        assert_eq!(pairs("((a)"), ["0:-", "1:3", ".", "1:1"]);
+        assert_eq!(pairs("a)"), [".", "!"]);
+        assert_eq!(pairs("(]"), ["0:-", "!"]);
+    }
+
+    fn cursor(src: &str, pos: usize) -> [Option<usize>; 2] {
+        let tokens = tokens(src);
+        cursor_pair(&tokens, &pair_brackets(src, &tokens), pos)
+    }
+
+    #[test]
+    fn cursor_bracket_and_its_partner() {
+        // on a bracket
+        assert_eq!(cursor("(a (b))", 3), [Some(3), Some(5)]);
+        // right after one
+        assert_eq!(cursor("(a (b))", 7), [Some(6), Some(0)]);
+        // on a bracket wins over the one before
+        assert_eq!(cursor("()", 1), [Some(1), Some(0)]);
+        assert_eq!(cursor("(a (b))", 2), [None, None]);
+        assert_eq!(cursor("(a", 0), [None, None]);
+        assert_eq!(cursor("a)", 1), [None, None]);
+    }
+}
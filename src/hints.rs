@@ -0,0 +1,122 @@
+use rustyline::hint::Hint;
+use std::collections::HashMap;
+use std::sync::{mpsc::channel, Arc, Mutex};
+use std::time::Duration;
+
+use crate::query::Querier;
+
+pub struct IHskHint {
+    display: String,
+    completion: Option<String>,
+}
+
+impl Hint for IHskHint {
+    fn display(&self) -> &str {
+        &self.display
+    }
+    fn completion(&self) -> Option<&str> {
+        self.completion.as_deref()
+    }
+}
+
+impl IHskHint {
+    /// Rest of a previous input, accepted with the right arrow
+    pub fn history(rest: String) -> Self {
+        Self {
+            display: rest.clone(),
+            completion: Some(rest),
+        }
+    }
+    /// Information only, nothing gets inserted
+    pub fn info(display: String) -> Self {
+        Self {
+            display,
+            completion: None,
+        }
+    }
+}
+
+/// Most recent history entry starting with `line`, minus `line`
+pub fn from_history<'h>(
+    line: &str,
+    history: impl DoubleEndedIterator<Item = &'h String>,
+) -> Option<String> {
+    history
+        .rev()
+        .find(|entry| entry.len() > line.len() && entry.starts_with(line))
+        .map(|entry| entry[line.len()..].to_owned())
+}
+
+type TypeCache = Arc<Mutex<HashMap<String, Option<String>>>>;
+
+/// How long a hint waits for a type it asked for
+/// a slower answer shows up on the next keystroke
+const TYPE_WAIT: Duration = Duration::from_millis(100);
+
+/// Types of identifiers, asked to the repl in the background
+/// so typing never waits for more than [`TYPE_WAIT`]
+#[derive(Default)]
+pub struct TypeHints {
+    cache: TypeCache,
+}
+
+impl TypeHints {
+    /// The cached type of `identifier`
+    /// if its unknown it is asked once, and shown if the answer comes quickly
+    pub fn get(
+        &self,
+        querier: &Querier,
+        identifier: &str,
+        type_of: fn(&Querier, &str) -> Option<String>,
+    ) -> Option<String> {
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(ty) = cache.get(identifier) {
+                return ty.clone();
+            }
+            // None marks the request as pending so its only sent once
+            cache.insert(identifier.to_owned(), None);
+        }
+
+        let (tx, rx) = channel();
+        let cache = self.cache.clone();
+        let querier = querier.clone();
+        let identifier = identifier.to_owned();
+        std::thread::spawn(move || {
+            let ty = type_of(&querier, &identifier);
+            cache.lock().unwrap().insert(identifier, ty.clone());
+            let _ = tx.send(ty);
+        });
+        rx.recv_timeout(TYPE_WAIT).ok().flatten()
+    }
+    /// Definitions may have changed
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_hints_come_from_the_latest_entry() {
+        let history: Vec<String> = ["map f xs", "map g ys", "mapM_ print xs", "map"]
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect();
+        assert_eq!(
+            from_history("map ", history.iter()).as_deref(),
+            Some("g ys")
+        );
+        assert_eq!(
+            from_history("mapM", history.iter()).as_deref(),
+            Some("_ print xs")
+        );
+        assert_eq!(
+            from_history("map", history.iter()).as_deref(),
+            Some("M_ print xs")
+        );
+        assert_eq!(from_history("filter", history.iter()), None);
+    }
+}
@@ -7,7 +7,7 @@ use rustyline::{
     error::ReadlineError,
     highlight::Highlighter,
     hint::Hinter,
-    validate::{MatchingBracketValidator, ValidationContext, ValidationResult, Validator},
+    validate::{ValidationContext, ValidationResult, Validator},
     Cmd, Context,
 };
 use rustyline::{Editor, Helper};
@@ -21,8 +21,24 @@ use racket::racket;
 mod gjs;
 use gjs::gjs;
 
+mod capture;
+mod cells;
+mod diagnostic;
+mod highlight;
+mod hints;
+use hints::{IHskHint, TypeHints};
+mod pager;
+mod query;
+mod sanitize;
+mod stats;
+use query::{Querier, Request};
+mod term;
+mod theme;
+use theme::Role;
+mod token;
+
 mod utils;
-use utils::StringTools;
+use utils::{fuzzy_anchor, is_subsequence, StringTools};
 
 #[derive(Debug, PartialEq)]
 enum Repl {
@@ -41,12 +57,32 @@ static REPL: Lazy<Repl> = Lazy::new(|| match std::env::args().nth(1) {
     None => Repl::Ghci,
 });
 
-#[derive(Default)]
 struct IHsk {
-    validator: MatchingBracketValidator,
     hints: HashSet<String>,
+    querier: Querier,
+    /// complete `fldr` to `foldr`, enabled with `IHSK_FUZZY=1`
+    fuzzy: bool,
+    /// show the type of the identifier under the cursor, enabled with `IHSK_TYPE_HINTS=1`
+    type_hints: Option<TypeHints>,
 }
 impl IHsk {
+    fn new(querier: Querier) -> Self {
+        Self {
+            hints: HashSet::new(),
+            querier,
+            fuzzy: matches!(std::env::var("IHSK_FUZZY").as_deref(), Ok("1")),
+            type_hints: match std::env::var("IHSK_TYPE_HINTS").as_deref() {
+                Ok("1") if *REPL == Repl::Ghci => Some(TypeHints::default()),
+                _ => None,
+            },
+        }
+    }
+    /// An input was evaluated, cached answers may be stale
+    fn evaluated(&self) {
+        if let Some(type_hints) = &self.type_hints {
+            type_hints.clear();
+        }
+    }
     fn add_to_hints(&mut self, line: &str) {
         line.split_non_alphanumeric().for_each(|item| {
             self.hints.insert(item);
@@ -56,7 +92,12 @@ impl IHsk {
 impl Helper for IHsk {}
 impl Validator for IHsk {
     fn validate(&self, ctx: &mut ValidationContext) -> Result<ValidationResult, ReadlineError> {
-        self.validator.validate(ctx)
+        match *REPL {
+            Repl::Ghci => Ok(ghci::validate(ctx.input())),
+            Repl::Smlnj => Ok(smlnj::validate(ctx.input())),
+            Repl::Racket => Ok(racket::validate(ctx.input())),
+            Repl::Gjs => Ok(gjs::validate(ctx.input())),
+        }
     }
 }
 impl Highlighter for IHsk {
@@ -71,13 +112,32 @@ impl Highlighter for IHsk {
             Repl::Gjs => gjs::highlight(line, pos).into(),
         }
     }
-    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
-        true
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        theme::paint(hint, Role::Hint).into()
+    }
+    /// Typing at the end can recolor the last token, moving next to a bracket shows its partner
+    fn highlight_char(&self, line: &str, pos: usize) -> bool {
+        pos == line.len() || highlight::near_bracket(line, pos)
     }
 }
 
 impl Hinter for IHsk {
-    type Hint = String;
+    type Hint = IHskHint;
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<IHskHint> {
+        if line.trim().is_empty() {
+            return None;
+        }
+        if pos == line.len() {
+            if let Some(rest) = hints::from_history(line, ctx.history().iter()) {
+                return Some(IHskHint::history(rest));
+            }
+        }
+
+        let type_hints = self.type_hints.as_ref()?;
+        let identifier = ghci::finished_identifier(line, pos)?;
+        let ty = type_hints.get(&self.querier, identifier, ghci::type_of)?;
+        Some(IHskHint::info(format!(" :: {}", ty)))
+    }
 }
 impl Completer for IHsk {
     type Candidate = String;
@@ -87,35 +147,75 @@ impl Completer for IHsk {
         pos: usize,
         _ctx: &Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
-        let last_word_start_pos = line[..pos].rfind(' ').map(|i| i + 1).unwrap_or(0);
-        let word_to_complete = &line[last_word_start_pos..pos];
+        let word_start = match *REPL {
+            Repl::Ghci => ghci::word_start(line, pos),
+            Repl::Racket => racket::word_start(line, pos),
+            Repl::Smlnj => smlnj::word_start(line, pos),
+            Repl::Gjs => gjs::word_start(line, pos),
+        };
+        let word_to_complete = &line[word_start..pos];
         if word_to_complete.is_empty() {
-            return Ok((0, vec![]));
+            return Ok((pos, vec![]));
         }
 
-        let mut candidates = vec![];
-        for hint in self.hints.iter() {
-            if hint.starts_with(word_to_complete) {
-                let hint = hint.replacen(word_to_complete, "", 1);
-                candidates.push(hint);
-            }
+        // fuzzy matching only anchors the first character after the qualifier
+        // so the repl is asked for everything starting with it
+        let query_prefix = if self.fuzzy {
+            fuzzy_anchor(word_to_complete)
+        } else {
+            word_to_complete
+        };
+
+        // ask the repl for the names it knows about
+        let backend_hints = match *REPL {
+            Repl::Ghci => ghci::complete(&self.querier, query_prefix),
+            Repl::Racket => racket::complete(&self.querier, &line[..word_start], query_prefix),
+            Repl::Smlnj => smlnj::complete(&self.querier, query_prefix),
+            Repl::Gjs => gjs::complete(&self.querier, query_prefix),
+        };
+
+        let names = self.hints.iter().chain(backend_hints.iter());
+        Ok((word_start, candidates(word_to_complete, self.fuzzy, names)))
+    }
+}
+
+/// Full names replacing `word`, exact prefixes first then fuzzy matches
+fn candidates<'n>(word: &str, fuzzy: bool, names: impl Iterator<Item = &'n String>) -> Vec<String> {
+    let anchor = fuzzy_anchor(word);
+    let mut prefixed = vec![];
+    let mut fuzzy_matches = vec![];
+    for name in names {
+        if name == word {
+            continue;
+        }
+        if name.starts_with(word) {
+            prefixed.push(name.clone());
+        } else if fuzzy && name.starts_with(anchor) && is_subsequence(word, name) {
+            fuzzy_matches.push(name.clone());
         }
-        Ok((pos, candidates))
     }
+    prefixed.sort();
+    prefixed.dedup();
+    fuzzy_matches.sort();
+    fuzzy_matches.dedup();
+
+    prefixed.extend(fuzzy_matches);
+    prefixed
 }
 
 fn main() {
     println!("Welcome to {:?} repl!", *REPL);
 
+    let (tx_in, rx_in) = channel();
+    let (tx_out, rx_out) = channel();
+
     let mut rl = Editor::new();
-    rl.set_helper(Some(IHsk::default()));
+    rl.set_helper(Some(IHsk::new(Querier::new(tx_in.clone()))));
     rl.bind_sequence(
         rustyline::KeyEvent(rustyline::KeyCode::Char('s'), rustyline::Modifiers::CTRL),
         Cmd::Newline,
     );
 
-    let (tx_in, rx_in) = channel();
-    let (tx_out, rx_out) = channel();
     std::thread::spawn(move || match *REPL {
         Repl::Smlnj => smlnj(rx_in, tx_out),
         Repl::Racket => racket(rx_in, tx_out),
@@ -125,17 +225,80 @@ fn main() {
 
     let _ = load_history(&mut rl);
 
+    // the last output that went over budget
+    let mut spill: Option<std::path::PathBuf> = None;
+    // every output in full and whether it failed, `:more 3` shows the third one
+    let mut outputs: Vec<(String, bool)> = vec![];
+    let mut last_folded = None;
+    // `:time on` prints what each evaluation cost
+    let mut timing = false;
     loop {
-        let readline = rl.readline("\x1b[1;33mIn: \x1b[0m");
+        let number = outputs.len() + 1;
+        let prompt_in = theme::paint(&format!("In [{}]: ", number), Role::PromptIn);
+        let readline = rl.readline(&prompt_in);
 
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
                 rl.helper_mut().unwrap().add_to_hints(&line);
-                tx_in.send(line.replace("\n", "") + "\n").unwrap();
-                let out = rx_out.recv().unwrap();
-                if !out.is_empty() {
-                    println!("\x1b[1;31mOut:\x1b[0m {}", out);
+                rl.helper().unwrap().evaluated();
+                if command(&line, ":page").is_some() {
+                    match &spill {
+                        Some(spill) => pager::open(spill),
+                        None => println!("{}", theme::paint("no truncated output", Role::Hint)),
+                    }
+                    continue;
+                }
+                if let Some(arg) = command(&line, ":time") {
+                    match arg {
+                        "on" => timing = true,
+                        "off" => timing = false,
+                        _ => println!("{}", theme::paint(":time on|off", Role::Hint)),
+                    }
+                    continue;
+                }
+                if let Some(arg) = command(&line, ":more") {
+                    let number = match arg.parse::<usize>() {
+                        Ok(number) => Some(number),
+                        Err(_) => last_folded,
+                    };
+                    let output = number
+                        .and_then(|number| Some((number, outputs.get(number.checked_sub(1)?)?)));
+                    match output {
+                        Some((number, (output, failed))) => {
+                            pager::show(&format!("{} {}", out_prompt(number, *failed), output))
+                        }
+                        None => println!("{}", theme::paint("no such output", Role::Hint)),
+                    }
+                    continue;
+                }
+                // `%out 3` refers to the result of cell 3
+                let cell = match *REPL {
+                    Repl::Ghci => query::resolve::<ghci::Ghci>(&line),
+                    Repl::Smlnj => query::resolve::<smlnj::Smlnj>(&line),
+                    Repl::Racket => query::resolve::<racket::Racket>(&line),
+                    Repl::Gjs => query::resolve::<gjs::Gjs>(&line),
+                };
+                tx_in.send(Request::Eval(cell, number)).unwrap();
+                let evaluation = rx_out.recv().unwrap();
+                if evaluation.spill.is_some() {
+                    spill = evaluation.spill.clone();
+                }
+                outputs.push((evaluation.output.clone(), evaluation.failed));
+                if !evaluation.output.is_empty() {
+                    let prompt = out_prompt(number, evaluation.failed);
+                    let truncated = evaluation.spill.is_some();
+                    let output = match pager::fold(&evaluation.output, number, truncated) {
+                        Some(folded) => {
+                            last_folded = Some(number);
+                            folded
+                        }
+                        None => evaluation.output,
+                    };
+                    println!("{} {}", prompt, output);
+                }
+                if let Some(stats) = evaluation.stats.filter(|_| timing) {
+                    println!("{}", theme::paint(&stats.footer(), Role::Hint));
                 }
             }
             Err(ReadlineError::Interrupted) => {}
@@ -146,6 +309,22 @@ fn main() {
     let _ = save_history(&mut rl);
 }
 
+/// `Out[3]:` in the error color when the evaluation failed
+fn out_prompt(number: usize, failed: bool) -> String {
+    let role = if failed { Role::Error } else { Role::PromptOut };
+    theme::paint(&format!("Out[{}]:", number), role)
+}
+
+/// The argument of an ihsk command, `:more 3` gives `3`
+fn command<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let rest = line.trim().strip_prefix(name)?;
+    if rest.is_empty() || rest.starts_with(' ') {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
 type CatchAll<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 fn load_history(rl: &mut Editor<IHsk>) -> CatchAll<()> {
     let ihsk_path = dirs_next::cache_dir().ok_or("")?.join("ihsk");
@@ -160,3 +339,36 @@ fn save_history(rl: &mut Editor<IHsk>) -> CatchAll<()> {
     let _ = rl.save_history(&ihsk_path.join("history.txt"));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn candidates_replace_the_whole_word() {
+        let line = "map Data.List.fo";
+        let start = ghci::word_start(line, line.len());
+        assert_eq!(start, 4);
+        let names = names(&[
+            "Data.List.foldr",
+            "Data.List.foldl'",
+            "Data.List.map",
+            "foldr",
+        ]);
+        assert_eq!(
+            candidates(&line[start..], false, names.iter()),
+            ["Data.List.foldl'", "Data.List.foldr"]
+        );
+    }
+
+    #[test]
+    fn fuzzy_candidates_come_after_prefixes() {
+        let names = names(&["foldr", "filter", "fldr", "fld", "map"]);
+        assert_eq!(candidates("fld", false, names.iter()), ["fldr"]);
+        assert_eq!(candidates("fld", true, names.iter()), ["fldr", "foldr"]);
+    }
+}
@@ -0,0 +1,98 @@
+use std::io::prelude::*;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::highlight;
+use crate::term;
+use crate::theme::{self, Role};
+
+/// Lines an output can take before it is folded
+/// `IHSK_FOLD=<lines>`, `0` never folds, by default it is what fits in the terminal
+fn fold_height() -> Option<usize> {
+    match std::env::var("IHSK_FOLD")
+        .ok()
+        .and_then(|lines| lines.parse().ok())
+    {
+        Some(0) => None,
+        Some(lines) => Some(lines),
+        // room for the prompt and the fold note
+        None => term::height().map(|rows| rows.saturating_sub(3).max(1)),
+    }
+}
+
+/// The first lines of an output too long for the terminal and a note on how to see the rest
+/// `number` is the output `:more <number>` shows, `truncated` is set when `:page` has even more
+pub fn fold(output: &str, number: usize, truncated: bool) -> Option<String> {
+    fold_to(output, fold_height()?, number, truncated)
+}
+
+/// [`fold`] past `height` lines
+fn fold_to(output: &str, height: usize, number: usize, truncated: bool) -> Option<String> {
+    let lines: Vec<&str> = output.lines().collect();
+    if lines.len() <= height {
+        return None;
+    }
+    let mut folded = lines[..height].join("\n");
+    // a style may be left open by the cut
+    if highlight::is_colored(&folded) {
+        folded.push_str("\x1b[0m");
+    }
+    let hint = if truncated {
+        format!(":more {} shows them, :page all of the output", number)
+    } else {
+        format!(":more {} shows all of it", number)
+    };
+    let note = format!("… {} more lines ({})", lines.len() - height, hint);
+    Some(folded + "\n" + &theme::paint(&note, Role::Hint) + "\n")
+}
+
+/// `$PAGER`, `less -R` by default so colors and search work
+fn pager() -> Command {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less -R".to_owned());
+    let mut words = pager.split_whitespace();
+    let mut command = Command::new(words.next().unwrap_or("less"));
+    command.args(words);
+    command
+}
+
+/// Shows `text` in the pager
+pub fn show(text: &str) {
+    let child = pager().stdin(Stdio::piped()).spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => return eprintln!("pager: {}", err),
+    };
+    // the pager can be quit before reading everything
+    let _ = child.stdin.take().unwrap().write_all(text.as_bytes());
+    let _ = child.wait();
+}
+
+/// Opens the file in the pager
+pub fn open(path: &Path) {
+    if let Err(err) = pager().arg(path).status() {
+        eprintln!("pager: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_outputs_are_folded() {
+        assert_eq!(fold_to("1\n2\n3\n", 3, 4, false), None);
+        let folded = fold_to("1\n2\n3\n4\n5\n", 2, 4, false).unwrap();
+        assert_eq!(
+            highlight::visible(&folded),
+            "1\n2\n… 3 more lines (:more 4 shows all of it)\n"
+        );
+        let folded = fold_to("1\n2\n3\n", 1, 4, true).unwrap();
+        assert!(highlight::visible(&folded).contains(":page all of the output"));
+    }
+
+    #[test]
+    fn a_style_left_open_is_closed() {
+        let folded = fold_to("\x1b[31m1\n2\x1b[0m\n", 1, 1, false).unwrap();
+        assert!(folded.starts_with("\x1b[31m1\x1b[0m\n"));
+    }
+}
@@ -0,0 +1,370 @@
+use std::borrow::Cow;
+use std::io::{prelude::*, BufReader};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use crate::capture::{self, Budget, Capture};
+use crate::cells::{self, Cell};
+use crate::diagnostic::{self, Diagnostic};
+use crate::sanitize;
+use crate::stats::{Sample, Stats};
+use crate::theme::{self, Role};
+use crate::token::Token;
+
+/// How long the editor waits for the repl to answer a query
+/// a slow answer is dropped so typing is never stuck
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+/// Printed on stderr once the repl is done with an input, what came before it is that input's
+const SYNC_MARK: &str = "ihsk:sync";
+/// How long stderr is waited on for the mark, a repl that doesn't print it doesn't hang ihsk
+const SYNC_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Messages sent from the editor to the repl thread
+pub enum Request {
+    /// User input and its cell number, its output is printed
+    /// the backend keeps the result as `_3` (or `out3`) when it can
+    Eval(Cell, usize),
+    /// Input issued by ihsk itself (completion, hints..)
+    /// its output is sent back on the given channel instead of being printed
+    /// only stdout is sent back
+    Query(String, Sender<String>),
+}
+
+/// What an `Eval` request answers
+pub struct Evaluation {
+    /// output and errors, ready to print
+    pub output: String,
+    /// the backend reported an error or an uncaught exception
+    pub failed: bool,
+    /// the whole output, when it went over budget
+    pub spill: Option<PathBuf>,
+    /// time and memory it took
+    pub stats: Option<Stats>,
+}
+
+impl Evaluation {
+    /// Notes the output cut past the budget, `:page` shows the whole of it
+    pub fn truncated(mut self, dropped: usize, spill: Option<PathBuf>) -> Self {
+        if dropped == 0 {
+            return self;
+        }
+        let hint = match spill {
+            Some(_) => " (:page shows all of it)",
+            None => "",
+        };
+        if !self.output.ends_with('\n') {
+            self.output.push('\n');
+        }
+        self.output += &theme::paint(
+            &format!("… output truncated, {} bytes more{}", dropped, hint),
+            Role::Hint,
+        );
+        self.output.push('\n');
+        self.spill = spill;
+        self
+    }
+
+    pub fn timed(mut self, stats: Stats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+}
+
+/// Side channel to the repl, used to ask it questions without disturbing the visible session
+#[derive(Clone)]
+pub struct Querier {
+    tx_in: Sender<Request>,
+}
+
+impl Querier {
+    pub fn new(tx_in: Sender<Request>) -> Self {
+        Self { tx_in }
+    }
+    pub fn query(&self, inp: &str) -> Option<String> {
+        let (tx, rx) = channel();
+        self.tx_in
+            .send(Request::Query(inp.to_owned() + "\n", tx))
+            .ok()?;
+        rx.recv_timeout(QUERY_TIMEOUT).ok()
+    }
+}
+
+/// What differs from one repl to the other, [`serve`] runs the session
+pub trait Backend {
+    /// The command starting the repl
+    const COMMAND: &'static str;
+    /// Prompts ending an answer, a leading newline isn't part of the prompt
+    const MARKS: &'static [&'static [u8]];
+    /// Inputs sent once the welcome message is read, each one is answered with a prompt
+    const SETUP: &'static [&'static str] = &[];
+    /// Prompt shown while a statement is unfinished, the lines of an input are then sent one by one
+    /// so the prompt after a first line that is finished on its own doesn't end the answer
+    const CONTINUATION: Option<&'static [u8]> = None;
+
+    /// Tokens of an input, references to results are looked for outside of its literals
+    fn tokens(src: &str) -> Vec<Token>;
+    /// The name the result of cell `number` is kept under
+    fn cell_name(number: usize) -> String {
+        format!("_{}", number)
+    }
+    /// Input printing `mark` on stderr, it leaves the result of the last input alone
+    fn sync(mark: &str) -> String;
+    /// The user input as it is sent, `number` is its cell
+    fn prepare(source: &str, number: usize) -> String;
+    /// A query as it is sent
+    fn prepare_query(inp: &str) -> String {
+        inp.to_owned()
+    }
+    /// The answer to `inp` without what the repl echoed of it
+    fn answer<'o>(out: &'o str, _inp: &str) -> Cow<'o, str> {
+        out.into()
+    }
+    /// Output of an evaluation colored by role
+    fn highlight_output(out: &str) -> String;
+    /// Errors reported in the output or on stderr, located in `source`
+    /// with what is left of the output and of stderr
+    fn diagnostics(out: &str, err: &str, _source: &str) -> (Vec<Diagnostic>, String, String) {
+        (vec![], out.to_owned(), err.to_owned())
+    }
+    /// Does what is left of the output report a failure, an uncaught exception
+    fn failed(_out: &str, _err: &str) -> bool {
+        false
+    }
+    /// Input keeping the result of cell `number` after it was evaluated
+    fn bind(_out: &str, _evaluation: &Evaluation, _source: &str, _number: usize) -> Option<String> {
+        None
+    }
+}
+
+/// `%out 3` and `_3` become the name the result of cell 3 is kept under
+pub fn resolve<B: Backend>(typed: &str) -> Cell {
+    cells::resolve(typed, &B::tokens(typed), B::cell_name)
+}
+
+/// Runs the repl and answers the requests of the editor until it hangs up
+pub fn serve<B: Backend>(rx_in: Receiver<Request>, tx_out: Sender<Evaluation>) {
+    let mut process = Command::new(B::COMMAND)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let pid = process.id();
+    let mut stdin = process.stdin.take().unwrap();
+    let mut stdout = BufReader::new(process.stdout.take().unwrap());
+
+    //read welcome message
+    capture::read_until(&mut stdout, B::MARKS, None);
+    for inp in B::SETUP {
+        send::<B>(&mut stdin, &mut stdout, inp, None);
+    }
+
+    let (tx_err, rx_err) = channel();
+    let mut stderr = process.stderr.take();
+    std::thread::spawn(move || {
+        let mut err = [0; 500];
+        loop {
+            let n = stderr.as_mut().unwrap().read(&mut err).unwrap();
+            let _ = tx_err.send(String::from_utf8_lossy(&err[..n]).into_owned());
+        }
+    });
+    let mut stderr = Stderr {
+        rx: rx_err,
+        pending: String::new(),
+    };
+
+    ctrlc::set_handler(move || {
+        use nix::{
+            sys::signal::{kill, Signal},
+            unistd::Pid,
+        };
+        let _ = kill(Pid::from_raw(pid as i32), Some(Signal::SIGINT));
+    })
+    .expect("Error setting Ctrl-C handler");
+
+    loop {
+        let request = match rx_in.recv() {
+            Ok(request) => request,
+            // program has ended
+            Err(_) => break,
+        };
+        let (inp, budget) = match &request {
+            Request::Eval(cell, number) => (
+                B::prepare(&cell.source, *number),
+                Some(Budget::for_eval(pid)),
+            ),
+            Request::Query(inp, _) => (B::prepare_query(inp), None),
+        };
+
+        let sample = Sample::take(pid);
+        let capture = send::<B>(&mut stdin, &mut stdout, &inp, budget);
+        let stats = sample.stats(pid);
+
+        let out = String::from_utf8_lossy(strip_prompt(&capture.out, B::MARKS));
+        let out = B::answer(&out, &inp);
+        let out = out.as_ref();
+        match request {
+            Request::Eval(cell, number) => {
+                let err = stderr.sync::<B>(&mut stdin, &mut stdout);
+                let evaluation = evaluation::<B>(out, &err, &cell);
+                let bind = B::bind(out, &evaluation, &cell.source, number);
+                let _ = tx_out.send(
+                    evaluation
+                        .truncated(capture.dropped, capture.spill)
+                        .timed(stats),
+                );
+                // `_3` is kept after the output is shown
+                if let Some(bind) = bind {
+                    send::<B>(&mut stdin, &mut stdout, &bind, None);
+                    // what the bind printed on stderr isn't the next answer's
+                    stderr.sync::<B>(&mut stdin, &mut stdout);
+                }
+            }
+            // a query may have timed out and stopped listening
+            // its stderr holds warnings and errors, not answers
+            Request::Query(_, tx) => {
+                let _ = tx.send(out.to_owned());
+                stderr.sync::<B>(&mut stdin, &mut stdout);
+            }
+        }
+    }
+}
+
+/// Output colored and errors drawn against the input as typed, the rest of stderr is kept
+fn evaluation<B: Backend>(out: &str, err: &str, cell: &Cell) -> Evaluation {
+    let (out, err) = (sanitize::clean(out), sanitize::clean(err));
+    let (mut diagnostics, out, err) = B::diagnostics(&out, &err, &cell.source);
+    cell.retarget(&mut diagnostics);
+    Evaluation {
+        output: B::highlight_output(&out) + &diagnostic::render(&diagnostics, &cell.typed) + &err,
+        failed: diagnostic::has_errors(&diagnostics) || B::failed(&out, &err),
+        spill: None,
+        stats: None,
+    }
+}
+
+/// The stderr of the repl, read on its own thread
+struct Stderr {
+    rx: Receiver<String>,
+    /// what came after the last mark
+    pending: String,
+}
+
+impl Stderr {
+    /// What the repl printed on stderr since the last sync
+    /// the repl is made to print a mark and stderr is read up to it, so late output isn't lost
+    fn sync<B: Backend>(&mut self, stdin: &mut impl Write, stdout: &mut impl Read) -> String {
+        send::<B>(stdin, stdout, &B::sync(SYNC_MARK), None);
+        let deadline = Instant::now() + SYNC_TIMEOUT;
+        loop {
+            if let Some(err) = self.take_marked() {
+                return err;
+            }
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(wait) {
+                Ok(chunk) => self.pending.push_str(&chunk),
+                Err(_) => return std::mem::take(&mut self.pending),
+            }
+        }
+    }
+
+    /// What was printed before the mark, the mark line is dropped
+    fn take_marked(&mut self) -> Option<String> {
+        let mark = format!("{}\n", SYNC_MARK);
+        let at = self.pending.find(&mark)?;
+        let err = self.pending[..at].to_owned();
+        self.pending.drain(..at + mark.len());
+        Some(err)
+    }
+}
+
+/// Writes `inp` and reads what the repl answers up to its prompt
+fn send<B: Backend>(
+    stdin: &mut impl Write,
+    stdout: &mut impl Read,
+    inp: &str,
+    budget: Option<Budget>,
+) -> Capture {
+    let lines: Vec<&str> = match B::CONTINUATION {
+        Some(_) => inp.split_inclusive('\n').collect(),
+        None => vec![inp],
+    };
+    let (last, first) = lines.split_last().unwrap_or((&"", &[]));
+    let mut echoed = vec![];
+    if let Some(continuation) = B::CONTINUATION {
+        let marks: Vec<&[u8]> = B::MARKS.iter().copied().chain(Some(continuation)).collect();
+        for line in first {
+            stdin.write_all(line.as_bytes()).unwrap();
+            echoed.extend(capture::read_until(stdout, &marks, None).out);
+        }
+    }
+    stdin.write_all(last.as_bytes()).unwrap();
+    let mut capture = capture::read_until(stdout, B::MARKS, budget);
+    echoed.append(&mut capture.out);
+    capture.out = echoed;
+    capture
+}
+
+/// The output without the prompt that ends it
+fn strip_prompt<'o>(out: &'o [u8], marks: &[&[u8]]) -> &'o [u8] {
+    marks
+        .iter()
+        .map(|mark| mark.strip_prefix(b"\n").unwrap_or(mark))
+        .find_map(|prompt| out.strip_suffix(prompt))
+        .unwrap_or(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output left as is, stderr starting with `!` reports a failure
+    struct Plain;
+
+    impl Backend for Plain {
+        const COMMAND: &'static str = "";
+        const MARKS: &'static [&'static [u8]] = &[];
+
+        fn tokens(_src: &str) -> Vec<Token> {
+            vec![]
+        }
+
+        fn sync(mark: &str) -> String {
+            mark.to_owned()
+        }
+
+        fn prepare(source: &str, _number: usize) -> String {
+            source.to_owned()
+        }
+
+        fn highlight_output(out: &str) -> String {
+            out.to_owned()
+        }
+
+        fn failed(_out: &str, err: &str) -> bool {
+            err.starts_with('!')
+        }
+    }
+
+    #[test]
+    fn stderr_follows_the_output() {
+        let cell = resolve::<Plain>("x");
+        let warned = evaluation::<Plain>("1\n", "warning\n", &cell);
+        assert_eq!(warned.output, "1\nwarning\n");
+        assert!(!warned.failed);
+        assert!(evaluation::<Plain>("", "! boom\n", &cell).failed);
+    }
+    #[test]
+    fn stderr_is_taken_up_to_the_mark() {
+        let (_tx, rx) = channel();
+        let mut stderr = Stderr {
+            rx,
+            pending: "warning\nihsk:sync\nlate".to_owned(),
+        };
+        assert_eq!(stderr.take_marked().as_deref(), Some("warning\n"));
+        assert_eq!(stderr.pending, "late");
+        assert_eq!(stderr.take_marked(), None);
+    }
+}
@@ -1,196 +1,473 @@
-use once_cell::sync::Lazy;
-use rustyline::Color;
-use std::io::{prelude::*, BufReader};
-use std::process::Stdio;
+use once_cell::sync::OnceCell;
+use rustyline::validate::ValidationResult;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 
+use crate::diagnostic::Diagnostic;
+use crate::highlight;
+use crate::query::{self, Backend, Evaluation, Querier, Request};
+use crate::theme::Role;
+use crate::token::{self, Brackets, Class, Token};
+use crate::utils;
+
+mod diagnostics;
+mod reader;
+
 const PRELUDE_MARK1: &[u8] = b"\n> ";
 const PRELUDE_MARK2: &[u8] = b"> ";
 
-pub fn racket(rx_in: mpsc::Receiver<String>, tx_out: mpsc::Sender<String>) {
-    let mut process = std::process::Command::new("racket")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .unwrap();
-    let pid = process.id();
-    let mut stdout = BufReader::new(process.stdout.as_mut().unwrap());
-
-    let mut out = vec![];
-    let mut buf = [0; 512];
-    //read welcome message
-    let mut read = |out: &mut Vec<u8>, mut buf: &mut [u8]| loop {
-        let n = stdout.read(&mut buf).unwrap();
-        out.extend(buf.iter().take(n));
-        if out.ends_with(PRELUDE_MARK1) || out.ends_with(PRELUDE_MARK2) {
-            break;
-        }
-    };
-    read(&mut out, &mut buf);
-
-    let (tx_err, rx_err) = mpsc::channel();
-    let mut stderr = process.stderr.take();
-    std::thread::spawn(move || {
-        let mut err = [0; 500];
-        loop {
-            let n = stderr.as_mut().unwrap().read(&mut err).unwrap();
-            let _ = tx_err.send(String::from_utf8(err[..n].to_vec()).unwrap());
-        }
-    });
+pub fn racket(rx_in: mpsc::Receiver<Request>, tx_out: mpsc::Sender<Evaluation>) {
+    query::serve::<Racket>(rx_in, tx_out)
+}
 
-    ctrlc::set_handler(move || {
-        use nix::{
-            sys::signal::{kill, Signal},
-            unistd::Pid,
-        };
-        let _ = kill(Pid::from_raw(pid as i32), Some(Signal::SIGINT));
-    })
-    .expect("Error setting Ctrl-C handler");
-
-    loop {
-        out.clear();
-        let inp = match rx_in.recv() {
-            Ok(inp) => inp,
-            // program has ended
-            _ => break,
-        };
+pub struct Racket;
 
-        process
-            .stdin
-            .as_mut()
-            .unwrap()
-            .write_all(inp.as_bytes())
-            .unwrap();
+impl Backend for Racket {
+    const COMMAND: &'static str = "racket";
+    const MARKS: &'static [&'static [u8]] = &[PRELUDE_MARK1, PRELUDE_MARK2];
+    const SETUP: &'static [&'static str] = &[
+        // the values printed by the last input, racket has no `it`
+        "(define ihsk:values '())\n",
+        "(define ihsk:last '())\n",
+        "(current-print (let ([print (current-print)]) \
+            (lambda (v) (unless (void? v) (set! ihsk:values (cons v ihsk:values))) (print v))))\n",
+        "(define (ihsk:sync mark) \
+            (set! ihsk:last (reverse ihsk:values)) (set! ihsk:values '()) \
+            (eprintf \"~a\\n\" mark) (flush-output (current-error-port)))\n",
+        "(define (ihsk:keep name) \
+            (when (= (length ihsk:last) 1) (namespace-set-variable-value! name (car ihsk:last) #t)))\n",
+    ];
 
-        read(&mut out, &mut buf);
+    fn tokens(src: &str) -> Vec<Token> {
+        reader::read(src)
+    }
 
-        // remove "> "
-        let out = String::from_utf8(out[..out.len() - 2].to_vec()).unwrap();
-        let err: String = rx_err.try_iter().collect();
-        tx_out.send(out + &err).unwrap();
+    /// the values printed by the last input are kept for the bind
+    fn sync(mark: &str) -> String {
+        format!("(ihsk:sync \"{}\")\n", mark)
     }
-}
 
-fn rand_color() -> (u8, u8, u8) {
-    rand::random()
-}
+    /// newlines are kept, they end `;` comments
+    fn prepare(source: &str, _number: usize) -> String {
+        source.to_owned() + "\n"
+    }
+
+    fn highlight_output(out: &str) -> String {
+        highlight_output(out)
+    }
+
+    /// the error is reported on stderr
+    fn diagnostics(out: &str, err: &str, source: &str) -> (Vec<Diagnostic>, String, String) {
+        let (diagnostics, rest) = diagnostics::parse(err, source);
+        (diagnostics, out.to_owned(), rest)
+    }
 
-static BRACKET_COLORS: Lazy<Vec<(u8, u8, u8)>> = Lazy::new(|| {
-    let mut colors = vec![];
-    for _ in 0..100 {
-        colors.push(rand_color());
+    /// The input is left alone, a single printed value is defined as `_3` after it
+    fn bind(_out: &str, evaluation: &Evaluation, _source: &str, number: usize) -> Option<String> {
+        if evaluation.failed {
+            return None;
+        }
+        Some(format!("(ihsk:keep '{})\n", Self::cell_name(number)))
     }
-    colors
-});
+}
+
+/// Every symbol bound in the repl namespace, one per line
+const MAPPED_SYMBOLS_QUERY: &str = "(for-each displayln (namespace-mapped-symbols))";
 
-struct BracketColorGen {
-    buffer: Vec<(u8, u8, u8)>,
-    color_idx: usize,
+/// Where collections are installed, one per line
+/// either `path` for a directory of collections
+/// or `name=path` for a single linked collection
+const COLLECTION_ROOTS_QUERY: &str = "(begin \
+    (for-each displayln (current-library-collection-paths)) \
+    (for ([f (find-library-collection-links)] #:when (and (path? f) (file-exists? f))) \
+      (for ([e (call-with-input-file f read)]) \
+        (define p (path->complete-path (cadr e) (path-only f))) \
+        (displayln (if (string? (car e)) (format \"~a=~a\" (car e) p) p)))))";
+
+enum CollectionRoot {
+    Dir(PathBuf),
+    Linked(String, PathBuf),
 }
 
-impl BracketColorGen {
-    fn new() -> Self {
-        Self {
-            buffer: Vec::new(),
-            color_idx: 0,
+static COLLECTION_ROOTS: OnceCell<Vec<CollectionRoot>> = OnceCell::new();
+
+/// Reads the input like racket does to know if its complete
+/// mismatched brackets are reported with their positions
+pub fn validate(input: &str) -> ValidationResult {
+    let tokens = reader::read(input);
+
+    if tokens.iter().any(|token| !token.closed) {
+        return ValidationResult::Incomplete;
+    }
+    match token::check_brackets(input, &tokens) {
+        Brackets::Balanced => (),
+        Brackets::Open(_) => return ValidationResult::Incomplete,
+        Brackets::Mismatched { open, close } => {
+            let (close_line, close_col) = utils::line_col(input, close);
+            let close_bracket = &input[close..close + 1];
+            let msg = match open {
+                Some(open) => {
+                    let (open_line, open_col) = utils::line_col(input, open);
+                    let open_token = tokens.iter().find(|token| token.start == open).unwrap();
+                    format!(
+                        " `{}` at {}:{} closed by `{}` at {}:{}",
+                        open_token.text(input),
+                        open_line,
+                        open_col,
+                        close_bracket,
+                        close_line,
+                        close_col
+                    )
+                }
+                None => format!(
+                    " unexpected `{}` at {}:{}",
+                    close_bracket, close_line, close_col
+                ),
+            };
+            return ValidationResult::Invalid(Some(msg));
         }
     }
-    fn next(&mut self, b: char) -> String {
-        let color = BRACKET_COLORS[self.color_idx];
-        self.color_idx += 1;
-        self.buffer.push(color);
-        b.to_string().as_str().rgb(color.0, color.1, color.2)
-    }
-    fn rev(&mut self, b: char) -> String {
-        if let Some(color) = self.buffer.pop() {
-            b.to_string().as_str().rgb(color.0, color.1, color.2)
-        } else {
-            b.to_string()
+
+    // a quote or `#;` waiting for its datum
+    let last = tokens
+        .iter()
+        .rev()
+        .find(|token| token.class != Class::Whitespace);
+    match last {
+        Some(last) if last.class == Class::Punctuation || last.text(input) == "#;" => {
+            ValidationResult::Incomplete
         }
+        _ => ValidationResult::Valid(None),
+    }
+}
+
+/// Anything but delimiters can be part of an identifier `set-mcar!` `string?` `racket/list`
+pub fn word_start(line: &str, pos: usize) -> usize {
+    utils::word_start(line, pos, |c| {
+        !c.is_whitespace() && !"()[]{}\",'`;|".contains(c)
+    })
+}
+
+/// Complete from the live namespace, or from the installed collections inside a `require`
+pub fn complete(querier: &Querier, line: &str, word: &str) -> Vec<String> {
+    if in_require(line) {
+        return complete_collection(querier, word);
+    }
+    match querier.query(MAPPED_SYMBOLS_QUERY) {
+        Some(out) => out
+            .lines()
+            .map(str::trim)
+            .filter(|symbol| symbol.starts_with(word))
+            .map(ToOwned::to_owned)
+            .collect(),
+        None => vec![],
     }
 }
 
-pub fn highlight(line: &str, _pos: usize) -> String {
-    let mut bracket_color = BracketColorGen::new();
-    let mut colored = String::new();
-
-    const DEFINE: &str = "define";
-    const SET: &str = "set!";
-    const BEGIN: &str = "begin";
-    const LAMBDA: &str = "lambda";
-    const LET: &str = "let";
-    const TRUE: &str = "#t";
-    const FALSE: &str = "#f";
-    const CONS: &str = "cons";
-    const LIST: &str = "list";
-    const LENGTH: &str = "length";
-    const AND: &str = "and";
-    const CDR: &str = "cdr";
-    const CAR: &str = "car";
-    const NULL: &str = "null";
-    const EMPTY: &str = "empty";
-    const MCAR: &str = "mcar";
-    const MCDR: &str = "mcdr";
-    const MCONS: &str = "mcons";
-    const SETMCAR: &str = "set-mcar!";
-    const SETMCDR: &str = "set-mcdr!";
-    const PAIR: &str = "pair";
-    const IF: &str = "if";
-    const ELSE: &str = "else";
-    const COND: &str = "cond";
-    const MPAIR: &str = "mpair";
-
-    let mut chars = line.chars().enumerate();
-
-    let mut parse = || -> Option<()> {
-        loop {
-            let (i, c) = chars.next()?;
-
-            let mut color_keyword_if_found = |keywords: Vec<&str>, color: fn(&str) -> String| {
-                for keyword in &keywords {
-                    if line.get(i..i + keyword.len()) == Some(keyword) {
-                        for _ in keyword.chars().skip(1) {
-                            chars.next().expect("Already checked");
-                        }
-                        colored.push_str(&color(keyword));
-                        return;
+/// Is the end of `line` inside a `(require ...)` form
+fn in_require(line: &str) -> bool {
+    match line.rfind("(require") {
+        Some(start) => !line[start..].contains(')'),
+        None => false,
+    }
+}
+
+fn complete_collection(querier: &Querier, word: &str) -> Vec<String> {
+    // a query that timed out (racket is still starting) is asked again next time
+    let roots = COLLECTION_ROOTS.get_or_try_init(|| {
+        let out = querier.query(COLLECTION_ROOTS_QUERY).ok_or(())?;
+        Ok(collection_roots(&out))
+    });
+    match roots {
+        Ok(roots) => collection_candidates(roots, word),
+        Err(()) => vec![],
+    }
+}
+
+/// The answer to [`COLLECTION_ROOTS_QUERY`]
+fn collection_roots(out: &str) -> Vec<CollectionRoot> {
+    out.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.find('=') {
+            Some(eq) => CollectionRoot::Linked(line[..eq].to_owned(), line[eq + 1..].into()),
+            None => CollectionRoot::Dir(line.into()),
+        })
+        .collect()
+}
+
+/// Collections and modules under `roots` completing `word`
+fn collection_candidates(roots: &[CollectionRoot], word: &str) -> Vec<String> {
+    // racket/li => look for `li*` in the `racket` collection
+    let (collection, prefix) = match word.rfind('/') {
+        Some(idx) => (&word[..idx], &word[idx + 1..]),
+        None => ("", word),
+    };
+
+    let mut candidates = vec![];
+    for root in roots {
+        let dir = match root {
+            CollectionRoot::Dir(path) => path.join(collection),
+            CollectionRoot::Linked(name, path) => {
+                if collection.is_empty() {
+                    if name.starts_with(prefix) {
+                        candidates.push(name.clone());
                     }
+                    continue;
+                }
+                match collection.strip_prefix(name.as_str()) {
+                    Some("") => path.clone(),
+                    Some(rest) if rest.starts_with('/') => path.join(&rest[1..]),
+                    _ => continue,
+                }
+            }
+        };
+        // top level collections are always directories
+        let only_dirs = collection.is_empty();
+        for module in collection_entries(&dir, only_dirs) {
+            if module.starts_with(prefix) {
+                if collection.is_empty() {
+                    candidates.push(module);
+                } else {
+                    candidates.push(format!("{}/{}", collection, module));
                 }
-                let first_char = keywords[0].chars().next().expect("No empty keyword");
-                colored.push(first_char);
-            };
-
-            match c {
-                '(' => colored.push_str(&bracket_color.next('(')),
-                '[' => colored.push_str(&bracket_color.next('[')),
-                ')' => colored.push_str(&bracket_color.rev(')')),
-                ']' => colored.push_str(&bracket_color.rev(']')),
-                'a' => color_keyword_if_found(vec![AND], Color::light_blue),
-                'b' => color_keyword_if_found(vec![BEGIN], Color::light_blue),
-                'c' => color_keyword_if_found(vec![CONS, CAR, CDR, COND], Color::light_blue),
-                'd' => color_keyword_if_found(vec![DEFINE], Color::light_blue),
-                'e' => color_keyword_if_found(vec![EMPTY, ELSE], |s| Color::rgb(s, 255, 100, 0)),
-                'i' => color_keyword_if_found(vec![IF], |s| Color::rgb(s, 255, 100, 0)),
-                'l' => color_keyword_if_found(vec![LAMBDA, LENGTH, LIST, LET], Color::light_blue),
-                'm' => color_keyword_if_found(vec![MPAIR, MCONS, MCAR, MCDR], Color::red),
-                'n' => color_keyword_if_found(vec![NULL], |s| Color::rgb(s, 255, 100, 0)),
-                'p' => color_keyword_if_found(vec![PAIR], Color::light_blue),
-                's' => color_keyword_if_found(vec![SETMCDR, SETMCAR, SET], Color::red),
-                '#' => color_keyword_if_found(vec![TRUE, FALSE], Color::green),
-                '+' => colored.push_str(&"+".yellow()),
-                '-' => colored.push_str(&"-".yellow()),
-                '/' => colored.push_str(&"/".yellow()),
-                '*' => colored.push_str(&"*".yellow()),
-                '=' => colored.push_str(&"=".yellow()),
-                '"' => colored.push_str(&"\"".yellow()),
-                '?' => colored.push_str(&"?".yellow()),
-                c => colored.push(c),
             }
         }
+    }
+    candidates
+}
+
+/// Sub collections and `.rkt` modules inside `dir`
+fn collection_entries(dir: &Path, only_dirs: bool) -> Vec<String> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
     };
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?;
+            if name.starts_with('.') || name == "compiled" {
+                return None;
+            }
+            if path.is_dir() {
+                Some(name.to_owned())
+            } else if !only_dirs {
+                Some(name.strip_suffix(".rkt")?.to_owned())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Syntactic forms and common procedures
+const FORMS: &[&str] = &[
+    "define",
+    "define-values",
+    "define-syntax",
+    "define-syntax-rule",
+    "define-struct",
+    "struct",
+    "lambda",
+    "λ",
+    "case-lambda",
+    "let",
+    "let*",
+    "letrec",
+    "let-values",
+    "let*-values",
+    "begin",
+    "begin0",
+    "cond",
+    "case",
+    "when",
+    "unless",
+    "and",
+    "or",
+    "not",
+    "do",
+    "quote",
+    "quasiquote",
+    "unquote",
+    "require",
+    "provide",
+    "module",
+    "module+",
+    "parameterize",
+    "with-handlers",
+    "match",
+    "for",
+    "for*",
+    "for/list",
+    "for*/list",
+    "for/fold",
+    "for/vector",
+    "for/hash",
+    "for/and",
+    "for/or",
+    "for/sum",
+    "syntax-rules",
+    "cons",
+    "car",
+    "cdr",
+    "list",
+    "length",
+    "pair",
+    "pair?",
+];
 
-    parse();
+const CONDITIONALS: &[&str] = &["if", "else", "empty", "null", "null?", "empty?"];
 
-    colored
+const MUTATIONS: &[&str] = &[
+    "set!",
+    "set-mcar!",
+    "set-mcdr!",
+    "mcons",
+    "mcar",
+    "mcdr",
+    "mpair",
+    "mpair?",
+    "set-box!",
+    "vector-set!",
+    "hash-set!",
+];
+
+const BOOLEANS: &[&str] = &["#t", "#f", "#true", "#false"];
+
+const OPERATORS: &[&str] = &["+", "-", "*", "/", "=", "<", ">", "<=", ">="];
+
+pub fn highlight(line: &str, pos: usize) -> String {
+    paint(line, Some(pos))
+}
+
+/// Printed values `'(1 2)` `#t` `"a"` read like input
+pub fn highlight_output(out: &str) -> String {
+    if highlight::is_colored(out) {
+        return out.to_owned();
+    }
+    paint(out, None)
+}
+
+fn paint(line: &str, cursor: Option<usize>) -> String {
+    // the reader knows which brackets are inside strings and comments
+    let tokens = reader::read(line);
+    highlight::paint(line, &tokens, cursor, |token| {
+        // `'sym` `#'f`
+        let quoted = line[..token.start].ends_with(&['\'', '`', ',', '@'][..]);
+        style(token.text(line), token.class, quoted)
+    })
+}
+
+/// Atoms are matched whole, `define-values` is not `define` + `-values`
+fn style(text: &str, class: Class, quoted: bool) -> Option<Role> {
+    let role = match class {
+        Class::String => Role::String,
+        Class::Char => Role::Char,
+        Class::Number => Role::Number,
+        Class::Comment => Role::Comment,
+        // `'` `` ` `` `,`
+        Class::Punctuation => Role::Punctuation,
+        Class::Identifier if quoted => Role::Constant,
+        Class::Identifier if FORMS.contains(&text) => Role::Keyword,
+        Class::Identifier if CONDITIONALS.contains(&text) => Role::Conditional,
+        Class::Identifier if MUTATIONS.contains(&text) => Role::Special,
+        Class::Identifier if BOOLEANS.contains(&text) => Role::Constant,
+        Class::Identifier if OPERATORS.contains(&text) => Role::Operator,
+        // `#:keyword` arguments
+        Class::Identifier if text.starts_with("#:") => Role::Constant,
+        _ => return None,
+    };
+    Some(role)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::{is_incomplete, is_valid};
+
+    #[test]
+    fn literals_and_comments_hide_brackets() {
+        assert!(is_valid(validate(r#"(display "(")"#)));
+        assert!(is_valid(validate(r"(list #\( #\))")));
+        assert!(is_valid(validate("(+ 1 2) ; (")));
+        assert!(is_valid(validate("#| ( |# (+ 1 2)")));
+        assert!(is_valid(validate("#;(a (b)) (+ 1 2)")));
+    }
+
+    #[test]
+    fn unfinished_input_is_incomplete() {
+        assert!(is_incomplete(validate("(define (f x)")));
+        assert!(is_incomplete(validate("(display \"abc")));
+        assert!(is_incomplete(validate("#| a")));
+        assert!(is_incomplete(validate("'")));
+        assert!(is_incomplete(validate("(+ 1 2) #;")));
+    }
+
+    #[test]
+    fn mismatched_brackets_give_their_positions() {
+        match validate("(let ([x 1)]\n  x)") {
+            ValidationResult::Invalid(Some(msg)) => {
+                assert_eq!(msg, " `[` at 1:7 closed by `)` at 1:11")
+            }
+            _ => panic!("expected a mismatch"),
+        }
+        match validate("(+ 1 2))") {
+            ValidationResult::Invalid(Some(msg)) => assert_eq!(msg, " unexpected `)` at 1:8"),
+            _ => panic!("expected a mismatch"),
+        }
+    }
+
+    #[test]
+    fn require_forms_complete_collections() {
+        assert!(in_require("(require racket/li"));
+        assert!(in_require("(define x 1) (require (only-in racket/li"));
+        assert!(!in_require("(require racket/list) (fi"));
+        assert!(!in_require("(define (f x) x"));
+    }
+
+    #[test]
+    fn collections_are_read_from_the_roots() {
+        let root = std::env::temp_dir().join(format!("ihsk-collections-{}", std::process::id()));
+        let linked = root.join("linked");
+        std::fs::create_dir_all(root.join("racket").join("private")).unwrap();
+        std::fs::create_dir_all(root.join("racket").join("compiled")).unwrap();
+        std::fs::create_dir_all(&linked).unwrap();
+        std::fs::write(root.join("racket").join("list.rkt"), "").unwrap();
+        std::fs::write(root.join("racket").join("notes.txt"), "").unwrap();
+        std::fs::write(linked.join("main.rkt"), "").unwrap();
+
+        let out = format!("{}\nmine={}\n\n", root.display(), linked.display());
+        let roots = collection_roots(&out);
+        let candidates = |word: &str| {
+            let mut candidates = collection_candidates(&roots, word);
+            candidates.sort();
+            candidates
+        };
+        assert_eq!(candidates("ra"), ["racket"]);
+        assert_eq!(candidates("m"), ["mine"]);
+        assert_eq!(candidates("racket/"), ["racket/list", "racket/private"]);
+        assert_eq!(candidates("racket/l"), ["racket/list"]);
+        assert_eq!(candidates("mine/ma"), ["mine/main"]);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn highlighting_keeps_the_text() {
+        for line in [
+            "(define (λ→ x) (string-append \"ü(\" x))",
+            "(list #\\( #\\λ #\\space \"a\\\"b\")",
+            "#| (( |# (set-mcar! p 1) ; (",
+            "#;(ignored (form)) (vector-ref #(1 2) 0)",
+            "(quote (a . b))\n  '(c ,d `e)",
+        ] {
+            for pos in [0, line.len() / 2, line.len()] {
+                if line.is_char_boundary(pos) {
+                    assert_eq!(highlight::visible(&highlight(line, pos)), line);
+                }
+            }
+            assert_eq!(highlight::visible(&highlight_output(line)), line);
+        }
+    }
 }
@@ -0,0 +1,205 @@
+use super::reader;
+use crate::diagnostic::{Diagnostic, Severity, Span};
+use crate::token::Class;
+use crate::utils;
+
+/// Racket prints an error on stderr as `name: message` and indented `  field: value` lines
+/// - a `srcloc: ` before the name is dropped, the repl counts positions over the whole session
+/// - the span is the `in:` expression or else the `name` atom, looked up in `source`
+/// - `context...:` and the stack after it are dropped
+///
+/// anything else on stderr (`eprintf`, logs) is kept as is
+pub fn parse(err: &str, source: &str) -> (Vec<Diagnostic>, String) {
+    let lines: Vec<&str> = err.lines().collect();
+    let start = match (0..lines.len()).find(|&idx| is_error(&lines[idx..])) {
+        Some(start) => start,
+        None => return (vec![], err.to_owned()),
+    };
+    let mut rest: String = lines[..start]
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let first = strip_srcloc(lines[start]);
+    let name = &first[..first.find(": ").unwrap()];
+
+    let mut message = vec![first.to_owned()];
+    let mut expression = None;
+    let mut idx = start + 1;
+    while idx < lines.len() {
+        let line = lines[idx];
+        let trimmed = line.trim();
+        if trimmed.starts_with("context...:") {
+            // the stack, one indented line per frame
+            idx += 1;
+            while idx < lines.len() && lines[idx].starts_with(' ') {
+                idx += 1;
+            }
+            break;
+        }
+        match line.find(|c: char| c != ' ') {
+            // ` cannot reference an identifier before its definition`
+            Some(1) => message[0] = format!("{} {}", message[0], trimmed),
+            // `  in: (if)`, `  expected: number?`
+            Some(2) => {
+                if let Some(expr) = trimmed.strip_prefix("in: ") {
+                    expression = expression.or(Some(expr));
+                }
+                message.push(trimmed.to_owned());
+            }
+            // the error is over
+            Some(0) => break,
+            // the value of the field above goes on
+            Some(_) => message.push(format!("  {}", trimmed)),
+            None => (),
+        }
+        idx += 1;
+    }
+    for line in &lines[idx..] {
+        rest.push_str(line);
+        rest.push('\n');
+    }
+
+    let span = expression
+        .and_then(|expr| source.find(expr).map(|start| (start, start + expr.len())))
+        .or_else(|| atom_offsets(source, name))
+        .map(|(start, end)| to_span(source, start, end));
+    let diagnostic = Diagnostic {
+        severity: Severity::Error,
+        span,
+        message: message.join("\n    "),
+        suggestions: vec![],
+    };
+    (vec![diagnostic], rest)
+}
+
+/// `stdin::12: if: bad syntax`, or `name: message` followed by what racket adds to an error:
+/// an indented continuation, a `  field: value` line or `  context...:`
+fn is_error(lines: &[&str]) -> bool {
+    let first = strip_srcloc(lines[0]);
+    match first.find(": ") {
+        Some(colon) if colon > 0 && !first[..colon].contains(' ') => (),
+        _ => return false,
+    }
+    if first.len() != lines[0].len() {
+        return true;
+    }
+    let next = match lines.get(1) {
+        Some(next) => *next,
+        None => return false,
+    };
+    // `  expected: number?`, the key has no spaces
+    let field = match next.strip_prefix("  ") {
+        Some(field) if field.trim_end() == "context...:" => true,
+        Some(field) => matches!(
+            (field.find(": "), field.find(' ')),
+            (Some(colon), Some(space)) if colon > 0 && colon < space
+        ),
+        None => false,
+    };
+    let continuation = next.starts_with(' ') && !next.starts_with("  ") && !next.trim().is_empty();
+    field || continuation
+}
+
+/// `stdin::12: ` `string:1:0: `
+fn strip_srcloc(line: &str) -> &str {
+    let colon = match line.find(": ") {
+        Some(colon) => colon,
+        None => return line,
+    };
+    let mut parts = line[..colon].split(':');
+    let _source = parts.next();
+    let numbers: Vec<&str> = parts.collect();
+    let is_srcloc = !numbers.is_empty()
+        && numbers
+            .iter()
+            .all(|part| part.chars().all(|c| c.is_ascii_digit()))
+        && numbers.iter().any(|part| !part.is_empty());
+    if is_srcloc {
+        &line[colon + 2..]
+    } else {
+        line
+    }
+}
+
+/// Byte range of the first atom spelled `name`
+fn atom_offsets(source: &str, name: &str) -> Option<(usize, usize)> {
+    reader::read(source)
+        .iter()
+        .find(|token| token.class == Class::Identifier && token.text(source) == name)
+        .map(|token| (token.start, token.end))
+}
+
+fn to_span(source: &str, start: usize, end: usize) -> Span {
+    // the end column is the last char, not the one after it
+    let last = source[..end]
+        .char_indices()
+        .last()
+        .map_or(start, |(idx, _)| idx.max(start));
+    Span {
+        start: utils::line_col(source, start),
+        end: utils::line_col(source, last),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exn_with_fields() {
+        let err = "+: contract violation\n  expected: number?\n  given: \"a\"\n  context...:\n   body of top-level\n";
+        let (diagnostics, rest) = parse(err, "(+ 1 \"a\")");
+        assert_eq!(rest, "");
+        assert_eq!(
+            diagnostics[0].message,
+            "+: contract violation\n    expected: number?\n    given: \"a\""
+        );
+        assert_eq!(
+            diagnostics[0].span,
+            Some(Span {
+                start: (1, 2),
+                end: (1, 2)
+            })
+        );
+    }
+
+    #[test]
+    fn srcloc_and_expression() {
+        let (diagnostics, _) = parse("stdin::7: if: bad syntax\n  in: (if)\n", "(if)");
+        assert_eq!(diagnostics[0].message, "if: bad syntax\n    in: (if)");
+        assert_eq!(
+            diagnostics[0].span,
+            Some(Span {
+                start: (1, 1),
+                end: (1, 4)
+            })
+        );
+    }
+
+    #[test]
+    fn continuation_line() {
+        let err = "x: undefined;\n cannot reference an identifier before its definition\n  in module: top-level\n";
+        let (diagnostics, _) = parse(err, "x");
+        assert_eq!(
+            diagnostics[0].message,
+            "x: undefined; cannot reference an identifier before its definition\n    in module: top-level"
+        );
+    }
+
+    #[test]
+    fn user_output_is_not_an_error() {
+        let (diagnostics, rest) = parse("warning: careful\n", "(eprintf \"warning: careful\\n\")");
+        assert!(diagnostics.is_empty());
+        assert_eq!(rest, "warning: careful\n");
+        let (diagnostics, _) = parse("note: a\nb: c\n", "");
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn output_before_and_after_the_error_is_kept() {
+        let err = "hello\nfoo: bad\n  context...:\n   body of top-level\nafter\n";
+        let (diagnostics, rest) = parse(err, "(f)");
+        assert_eq!(diagnostics[0].message, "foo: bad");
+        assert_eq!(rest, "hello\nafter\n");
+    }
+}
@@ -0,0 +1,315 @@
+use crate::token::{Class, Cursor, Token};
+
+/// Chars that end an atom
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[]{}\",'`;".contains(c)
+}
+
+/// Splits racket source into tokens, the tokens cover the whole input
+/// - brackets, including the `#(` `#hash(` prefixed ones
+/// - strings, `#rx"` `#px"` regexps and `#"` byte strings
+/// - `#\(` chars
+/// - `;` `#| |#` comments and `#;` datum comments, the commented datum is a comment too
+/// - quote prefixes `'` `` ` `` `,` `,@` `#'`..
+/// - everything else is an atom
+pub fn read(src: &str) -> Vec<Token> {
+    let mut cursor = Cursor::new(src);
+    let mut tokens = vec![];
+    while let Some(c) = cursor.peek() {
+        let start = cursor.pos();
+        let token = if c.is_whitespace() {
+            cursor.eat_while(char::is_whitespace);
+            cursor.token(Class::Whitespace, start)
+        } else if c == ';' {
+            cursor.eat_while(|c| c != '\n');
+            cursor.token(Class::Comment, start)
+        } else if cursor.starts_with("#|") {
+            block_comment(&mut cursor)
+        } else if cursor.starts_with("#;") {
+            cursor.skip(2);
+            cursor.token(Class::Comment, start)
+        } else if cursor.starts_with("#\\") {
+            cursor.skip(2);
+            // `#\(` `#\space` `#\λ` `#λ`
+            if let Some(c) = cursor.bump() {
+                if c.is_alphanumeric() {
+                    cursor.eat_while(|c| !is_delimiter(c));
+                }
+            }
+            cursor.token(Class::Char, start)
+        } else if c == '"' {
+            string(&mut cursor, start)
+        } else if let Some(prefix) = string_prefix(cursor.rest()) {
+            cursor.skip(prefix);
+            string(&mut cursor, start)
+        } else if let Some(len) = bracket_prefix(cursor.rest()) {
+            cursor.skip(len);
+            cursor.bump();
+            cursor.token(Class::OpenBracket, start)
+        } else if "([{".contains(c) {
+            cursor.bump();
+            cursor.token(Class::OpenBracket, start)
+        } else if ")]}".contains(c) {
+            cursor.bump();
+            cursor.token(Class::CloseBracket, start)
+        } else if let Some(len) = quote_prefix(cursor.rest()) {
+            cursor.skip(len);
+            cursor.token(Class::Punctuation, start)
+        } else {
+            atom(&mut cursor);
+            let class = if is_number(cursor.since(start)) {
+                Class::Number
+            } else {
+                Class::Identifier
+            };
+            cursor.token(class, start)
+        };
+        tokens.push(token);
+    }
+    comment_datums(src, &mut tokens);
+    tokens
+}
+
+/// `42` `-1.5` `1/2` `6.02e23` `#x1F` `+inf.0`
+fn is_number(atom: &str) -> bool {
+    let lower = atom.to_lowercase();
+    // radix and exactness prefixes
+    if ["#x", "#b", "#o", "#d", "#e", "#i"]
+        .iter()
+        .any(|prefix| lower.starts_with(prefix))
+    {
+        return true;
+    }
+    if ["+inf.0", "-inf.0", "+nan.0", "-nan.0", "+inf.f", "-inf.f"].contains(&lower.as_str()) {
+        return true;
+    }
+    let digits = lower.trim_start_matches(&['+', '-'][..]);
+    digits.starts_with(|c: char| c.is_ascii_digit())
+        || (digits.starts_with('.') && digits[1..].starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// `#rx"` `#px#"` `#"`: length of what comes before the `"`
+fn string_prefix(rest: &str) -> Option<usize> {
+    ["#rx#\"", "#px#\"", "#rx\"", "#px\"", "#\""]
+        .iter()
+        .find(|prefix| rest.starts_with(*prefix))
+        .map(|prefix| prefix.len() - 1)
+}
+
+/// `#(` `#hash(` `#s[`..: length of what comes before the bracket
+fn bracket_prefix(rest: &str) -> Option<usize> {
+    let rest = rest.strip_prefix('#')?;
+    let name_len = rest
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(rest.len());
+    match rest[name_len..].chars().next() {
+        Some('(') | Some('[') | Some('{') => Some(1 + name_len),
+        _ => None,
+    }
+}
+
+fn quote_prefix(rest: &str) -> Option<usize> {
+    [",@", "#,@", "#'", "#`", "#,", "'", "`", ","]
+        .iter()
+        .find(|prefix| rest.starts_with(*prefix))
+        .map(|prefix| prefix.len())
+}
+
+/// `#| |#` comments nest
+fn block_comment(cursor: &mut Cursor) -> Token {
+    let start = cursor.pos();
+    let mut depth = 0;
+    while !cursor.is_eof() {
+        if cursor.starts_with("#|") {
+            depth += 1;
+            cursor.skip(2);
+        } else if cursor.starts_with("|#") {
+            depth -= 1;
+            cursor.skip(2);
+            if depth == 0 {
+                return cursor.token(Class::Comment, start);
+            }
+        } else {
+            cursor.bump();
+        }
+    }
+    Token {
+        closed: false,
+        ..cursor.token(Class::Comment, start)
+    }
+}
+
+/// From the opening `"`, strings can span several lines
+fn string(cursor: &mut Cursor, start: usize) -> Token {
+    cursor.bump();
+    while let Some(c) = cursor.bump() {
+        match c {
+            '"' => return cursor.token(Class::String, start),
+            '\\' => {
+                cursor.bump();
+            }
+            _ => (),
+        }
+    }
+    Token {
+        closed: false,
+        ..cursor.token(Class::String, start)
+    }
+}
+
+/// Symbols, numbers, `#t`, `#:keyword`.. `|a b|` and `\ ` quote delimiters
+fn atom(cursor: &mut Cursor) {
+    let mut verbatim = false;
+    while let Some(c) = cursor.peek() {
+        if !verbatim && is_delimiter(c) {
+            break;
+        }
+        cursor.bump();
+        match c {
+            '|' => verbatim = !verbatim,
+            '\\' if !verbatim => {
+                cursor.bump();
+            }
+            _ => (),
+        }
+    }
+}
+
+/// The datum after `#;` is commented out
+fn comment_datums(src: &str, tokens: &mut [Token]) {
+    let mut idx = 0;
+    while idx < tokens.len() {
+        let is_datum_comment = tokens[idx].text(src) == "#;";
+        idx += 1;
+        if !is_datum_comment {
+            continue;
+        }
+        // an unfinished datum keeps its brackets so the input reads as incomplete
+        let end = match datum_end(tokens, idx) {
+            Some(end) => end,
+            None => return,
+        };
+        for token in &mut tokens[idx..end] {
+            if token.class != Class::Whitespace {
+                token.class = Class::Comment;
+            }
+        }
+        idx = end;
+    }
+}
+
+/// Index after the datum starting at (or after the trivia at) `idx`
+/// None if the datum isn't finished
+fn datum_end(tokens: &[Token], mut idx: usize) -> Option<usize> {
+    while idx < tokens.len() && tokens[idx].is_trivia() {
+        idx += 1;
+    }
+    if idx == tokens.len() {
+        return None;
+    }
+    match tokens[idx].class {
+        // `'(a b)`
+        Class::Punctuation => datum_end(tokens, idx + 1),
+        Class::OpenBracket => {
+            let mut depth = 0;
+            for (i, token) in tokens.iter().enumerate().skip(idx) {
+                match token.class {
+                    Class::OpenBracket => depth += 1,
+                    Class::CloseBracket => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i + 1);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            None
+        }
+        _ => Some(idx + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tokens that aren't whitespace
+    fn tokens(src: &str) -> Vec<(Class, &str)> {
+        read(src)
+            .into_iter()
+            .filter(|token| token.class != Class::Whitespace)
+            .map(|token| (token.class, token.text(src)))
+            .collect()
+    }
+
+    #[test]
+    fn brackets_in_strings_and_chars_are_not_brackets() {
+        assert_eq!(
+            tokens(r#"(display "(" #\()"#),
+            vec![
+                (Class::OpenBracket, "("),
+                (Class::Identifier, "display"),
+                (Class::String, r#""(""#),
+                (Class::Char, r"#\("),
+                (Class::CloseBracket, ")"),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            tokens("; (\n#| ( #| |# |# x"),
+            vec![
+                (Class::Comment, "; ("),
+                (Class::Comment, "#| ( #| |# |#"),
+                (Class::Identifier, "x"),
+            ]
+        );
+        assert!(!read("#| a")[0].closed);
+    }
+
+    #[test]
+    fn datum_comments_cover_the_datum() {
+        let classes: Vec<Class> = tokens("#;(a (b)) c")
+            .into_iter()
+            .map(|(class, _)| class)
+            .collect();
+        assert!(classes[..classes.len() - 1]
+            .iter()
+            .all(|&class| class == Class::Comment));
+        assert_eq!(classes.last(), Some(&Class::Identifier));
+    }
+
+    #[test]
+    fn atoms_are_delimited() {
+        assert_eq!(
+            tokens("(define-values (λ) 1.5)"),
+            vec![
+                (Class::OpenBracket, "("),
+                (Class::Identifier, "define-values"),
+                (Class::OpenBracket, "("),
+                (Class::Identifier, "λ"),
+                (Class::CloseBracket, ")"),
+                (Class::Number, "1.5"),
+                (Class::CloseBracket, ")"),
+            ]
+        );
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            tokens("'(a) #'f"),
+            vec![
+                (Class::Punctuation, "'"),
+                (Class::OpenBracket, "("),
+                (Class::Identifier, "a"),
+                (Class::CloseBracket, ")"),
+                (Class::Punctuation, "#'"),
+                (Class::Identifier, "f"),
+            ]
+        );
+    }
+}
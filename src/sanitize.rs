@@ -0,0 +1,203 @@
+use once_cell::sync::Lazy;
+
+use crate::term::{self, Colors};
+
+/// What happens to the escape sequences and control chars of the child output
+/// colors (SGR sequences) always go through, unless the terminal is plain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// drop them
+    Strip,
+    /// show them as text `^[[2J` `^G`
+    Escape,
+    /// pass everything through untouched
+    Keep,
+}
+
+/// Chosen with `IHSK_ESCAPES=strip|escape|keep`, strip by default
+pub static POLICY: Lazy<Policy> = Lazy::new(|| match std::env::var("IHSK_ESCAPES").as_deref() {
+    Ok("escape") => Policy::Escape,
+    Ok("keep") => Policy::Keep,
+    _ => Policy::Strip,
+});
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+
+/// Makes child output safe to print above the editor
+/// - SGR colors are reduced to what the terminal shows, like the colors of ihsk,
+///   and dropped when it shows none
+/// - other CSI, OSC (title..) and DCS sequences follow the policy
+/// - other control chars follow the policy, newlines and tabs are kept
+/// - a `\r` redraws the line, only what was drawn last is kept (progress bars)
+pub fn clean(text: &str) -> String {
+    clean_with(text, *POLICY, *term::COLORS)
+}
+
+fn clean_with(text: &str, policy: Policy, colors: Colors) -> String {
+    if policy == Policy::Keep {
+        return text.to_owned();
+    }
+    let mut cleaned = String::with_capacity(text.len());
+    for (idx, line) in text.split('\n').enumerate() {
+        if idx > 0 {
+            cleaned.push('\n');
+        }
+        let line = redrawn(line);
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            let len = if c == ESC {
+                sequence_len(rest)
+            } else {
+                c.len_utf8()
+            };
+            let piece = &rest[..len];
+            rest = &rest[len..];
+            if c == ESC && is_sgr(piece) {
+                // `ESC [ m` is a reset too
+                let params = match &piece[2..piece.len() - 1] {
+                    "" => "0",
+                    params => params,
+                };
+                if let Some(sgr) = term::downgrade(params, colors) {
+                    cleaned.push_str(&format!("\x1b[{}m", sgr));
+                }
+            } else if c == ESC || (c.is_control() && c != '\t') {
+                if policy == Policy::Escape {
+                    cleaned.push_str(&caret_notation(piece));
+                }
+            } else {
+                cleaned.push_str(piece);
+            }
+        }
+    }
+    cleaned
+}
+
+/// The part of the line drawn after the last `\r`, `\r\n` is a plain line end
+fn redrawn(line: &str) -> &str {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    match line.rfind('\r') {
+        Some(cr) if cr + 1 < line.len() => &line[cr + 1..],
+        _ => line.trim_end_matches('\r'),
+    }
+}
+
+/// Length of the escape sequence at the start of `text`
+fn sequence_len(text: &str) -> usize {
+    let body = &text[1..];
+    match body.chars().next() {
+        // CSI: parameters and intermediates then a final byte
+        Some('[') => {
+            let end = body[1..]
+                .find(|c: char| ('\x40'..='\x7e').contains(&c))
+                .map_or(body.len(), |idx| 1 + idx + 1);
+            1 + end
+        }
+        // OSC DCS SOS PM APC: until BEL or `ESC \`
+        Some(']') | Some('P') | Some('X') | Some('^') | Some('_') => {
+            let end = body.find(&[BEL, ESC][..]).map_or(body.len(), |idx| {
+                if body[idx..].starts_with("\x1b\\") {
+                    idx + 2
+                } else if body[idx..].starts_with(BEL) {
+                    idx + 1
+                } else {
+                    idx
+                }
+            });
+            1 + end
+        }
+        Some(c) => 1 + c.len_utf8(),
+        None => 1,
+    }
+}
+
+/// `ESC [ 1;31 m`
+fn is_sgr(sequence: &str) -> bool {
+    match sequence
+        .strip_prefix("\x1b[")
+        .and_then(|s| s.strip_suffix('m'))
+    {
+        Some(params) => params.chars().all(|c| c.is_ascii_digit() || c == ';'),
+        None => false,
+    }
+}
+
+/// `^[[2J` `^G` `^?`
+fn caret_notation(piece: &str) -> String {
+    piece
+        .chars()
+        .map(|c| match c {
+            '\x7f' => "^?".to_owned(),
+            c if (c as u32) < 0x20 => format!("^{}", ((c as u8) + b'@') as char),
+            c if c.is_control() => format!("<U+{:04X}>", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_are_downgraded() {
+        let text = "\x1b[1;38;2;255;0;0mred\x1b[0m";
+        assert_eq!(clean_with(text, Policy::Strip, Colors::TrueColor), text);
+        assert_eq!(
+            clean_with(text, Policy::Strip, Colors::Ansi256),
+            "\x1b[1;38;5;196mred\x1b[0m"
+        );
+        assert_eq!(
+            clean_with(text, Policy::Strip, Colors::Ansi16),
+            "\x1b[1;91mred\x1b[0m"
+        );
+        assert_eq!(
+            clean_with("\x1b[38;5;166mx\x1b[m", Policy::Strip, Colors::Ansi256),
+            "\x1b[38;5;166mx\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn no_color_keeps_attributes_only() {
+        assert_eq!(
+            clean_with("\x1b[1;31mx\x1b[0m", Policy::Strip, Colors::Attributes),
+            "\x1b[1mx\x1b[0m"
+        );
+        assert_eq!(
+            clean_with("\x1b[38;5;166mx", Policy::Strip, Colors::Attributes),
+            "x"
+        );
+    }
+
+    #[test]
+    fn plain_drops_colors() {
+        assert_eq!(
+            clean_with("\x1b[31mx\x1b[0m", Policy::Strip, Colors::Plain),
+            "x"
+        );
+        assert_eq!(
+            clean_with("\x1b[31mx\x1b[0m", Policy::Escape, Colors::Plain),
+            "x"
+        );
+    }
+
+    #[test]
+    fn other_sequences_follow_the_policy() {
+        let text = "\x1b[2Ja\x1b]0;title\x07b\x07";
+        assert_eq!(clean_with(text, Policy::Strip, Colors::Ansi16), "ab");
+        assert_eq!(
+            clean_with(text, Policy::Escape, Colors::Ansi16),
+            "^[[2Ja^[]0;title^Gb^G"
+        );
+        assert_eq!(clean_with(text, Policy::Keep, Colors::Ansi16), text);
+    }
+
+    #[test]
+    fn carriage_returns_keep_the_last_redraw() {
+        assert_eq!(
+            clean_with("10%\r50%\r100%\r\ndone\r\n", Policy::Strip, Colors::Ansi16),
+            "100%\ndone\n"
+        );
+    }
+}
@@ -1,109 +1,473 @@
-use rustyline::Color;
-use std::io::{prelude::*, BufReader};
-use std::process::Stdio;
+use rustyline::validate::ValidationResult;
+use std::collections::HashMap;
 use std::sync::mpsc;
 
+use crate::diagnostic::Diagnostic;
+use crate::highlight;
+use crate::query::{self, Backend, Evaluation, Querier, Request};
+use crate::theme::Role;
+use crate::token::{self, Class, Token};
+use crate::utils;
+
+mod diagnostics;
+mod lexer;
+
 const PRELUDE_MARK: &[u8] = b"\n- ";
 
-pub fn smlnj(rx_in: mpsc::Receiver<String>, tx_out: mpsc::Sender<String>) {
-    let mut process = std::process::Command::new("smlnj")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .unwrap();
-    let pid = process.id();
-    let mut stdout = BufReader::new(process.stdout.as_mut().unwrap());
-
-    let mut out = vec![];
-    let mut buf = [0; 512];
-    //read welcome message
-    let mut read = |out: &mut Vec<u8>, mut buf: &mut [u8]| loop {
-        let n = stdout.read(&mut buf).unwrap();
-        out.extend(buf.iter().take(n));
-        if out.ends_with(PRELUDE_MARK) {
-            break;
+pub fn smlnj(rx_in: mpsc::Receiver<Request>, tx_out: mpsc::Sender<Evaluation>) {
+    query::serve::<Smlnj>(rx_in, tx_out)
+}
+
+pub struct Smlnj;
+
+impl Backend for Smlnj {
+    const COMMAND: &'static str = "smlnj";
+    const MARKS: &'static [&'static [u8]] = &[PRELUDE_MARK];
+
+    fn tokens(src: &str) -> Vec<Token> {
+        lexer::lex(src)
+    }
+
+    /// sml identifiers start with a letter
+    fn cell_name(number: usize) -> String {
+        format!("out{}", number)
+    }
+
+    /// `val _` leaves `it` alone
+    fn sync(mark: &str) -> String {
+        format!(
+            "val _ = (TextIO.output (TextIO.stdErr, \"{}\\n\"); TextIO.flushOut TextIO.stdErr);\n",
+            mark
+        )
+    }
+
+    fn prepare(source: &str, _number: usize) -> String {
+        prepare(source)
+    }
+
+    fn prepare_query(inp: &str) -> String {
+        prepare(inp)
+    }
+
+    fn highlight_output(out: &str) -> String {
+        highlight_output(out)
+    }
+
+    /// errors and warnings are printed on stdout, between the values
+    fn diagnostics(out: &str, err: &str, source: &str) -> (Vec<Diagnostic>, String, String) {
+        let (diagnostics, rest) = diagnostics::parse(out, source);
+        (diagnostics, rest, err.to_owned())
+    }
+
+    fn failed(out: &str, _err: &str) -> bool {
+        out.contains("uncaught exception")
+    }
+
+    /// `it` is overwritten by the next expression
+    fn bind(out: &str, evaluation: &Evaluation, _source: &str, number: usize) -> Option<String> {
+        if evaluation.failed || !out.lines().any(|line| line.starts_with("val it = ")) {
+            return None;
         }
+        Some(format!("val {} = it;\n", Self::cell_name(number)))
+    }
+}
+
+/// Send the declaration on one line (so smlnj doesn't print `=` continuation prompts)
+/// and auto insert the final `;` unless its already there
+fn prepare(inp: &str) -> String {
+    let mut inp = inp.lines().collect::<Vec<_>>().join(" ");
+    let tokens = lexer::lex(&inp);
+    if token::last_significant(&tokens).map(|last| last.text(&inp)) != Some(";") {
+        inp.push(';');
+    }
+    inp + "\n"
+}
+
+/// Keywords opening a block closed by `end`
+const BLOCK_OPENERS: &[&str] = &["let", "local", "struct", "sig", "abstype"];
+
+/// Tokens after which the declaration can't be finished
+const CONTINUATIONS: &[&str] = &[
+    "=",
+    "=>",
+    "->",
+    "|",
+    ":",
+    ":>",
+    ",",
+    "of",
+    "in",
+    "then",
+    "else",
+    "andalso",
+    "orelse",
+    "fn",
+    "fun",
+    "val",
+    "and",
+    "do",
+    "if",
+    "case",
+    "handle",
+    "raise",
+    "while",
+    "op",
+    "datatype",
+    "type",
+    "structure",
+    "signature",
+    "functor",
+    "exception",
+    "open",
+];
+
+/// Asks for more lines until the top level declaration is closed
+/// - inside a `(* *)` comment, a string gap or brackets
+/// - inside `let`, `local`, `struct`, `sig`.. not closed by `end` yet
+/// - after a token that needs something to follow (`=`, `of`, `then`..)
+pub fn validate(input: &str) -> ValidationResult {
+    let tokens = lexer::lex(input);
+    // comments and `\` string gaps go on over the next line
+    let continues = |token: &Token| {
+        token.class == Class::Comment || token.text(input).trim_end().ends_with('\\')
     };
-    read(&mut out, &mut buf);
-
-    let (tx_err, rx_err) = mpsc::channel();
-    let mut stderr = process.stderr.take();
-    std::thread::spawn(move || {
-        let mut err = [0; 500];
-        loop {
-            let n = stderr.as_mut().unwrap().read(&mut err).unwrap();
-            let _ = tx_err.send(String::from_utf8(err[..n].to_vec()).unwrap());
+    if let Some(result) = token::check_open(input, &tokens, continues) {
+        return result;
+    }
+
+    let mut depth = 0;
+    for token in tokens.iter().filter(|token| token.class == Class::Keyword) {
+        match token.text(input) {
+            keyword if BLOCK_OPENERS.contains(&keyword) => depth += 1,
+            "end" if depth == 0 => {
+                return ValidationResult::Invalid(Some(" `end` without a matching block".into()))
+            }
+            "end" => depth -= 1,
+            _ => (),
         }
-    });
+    }
+    if depth > 0 {
+        return ValidationResult::Incomplete;
+    }
 
-    ctrlc::set_handler(move || {
-        use nix::{
-            sys::signal::{kill, Signal},
-            unistd::Pid,
-        };
-        let _ = kill(Pid::from_raw(pid as i32), Some(Signal::SIGINT));
+    match token::last_significant(&tokens) {
+        Some(last) if CONTINUATIONS.contains(&last.text(input)) => ValidationResult::Incomplete,
+        _ => ValidationResult::Valid(None),
+    }
+}
+
+/// Long identifiers `List.map` are completed as one word
+pub fn word_start(line: &str, pos: usize) -> usize {
+    utils::word_start(line, pos, |c| {
+        c.is_alphanumeric() || c == '_' || c == '\'' || c == '.'
     })
-    .expect("Error setting Ctrl-C handler");
-
-    loop {
-        out.clear();
-        let mut inp = match rx_in.recv() {
-            Ok(inp) => inp,
-            // program has ended
-            _ => break,
+}
+
+/// Structure the members are copied into so the repl prints their signature
+/// the name is left in the top level environment, emptied right after each query
+const COMPLETION_STRUCTURE: &str = "IHskCompletion__";
+
+/// Complete the members of a structure, `List.ma` => `List.map`
+pub fn complete(querier: &Querier, word: &str) -> Vec<String> {
+    let dot = match word.rfind('.') {
+        Some(dot) => dot,
+        None => return vec![],
+    };
+    let (structure, prefix) = (&word[..dot], &word[dot + 1..]);
+    if structure.is_empty() || !structure.split('.').all(is_identifier) {
+        return vec![];
+    }
+
+    // the second declaration drops the copied members, its signature is printed after the first
+    let query = format!(
+        "structure {0} = struct open {1} end; structure {0} = struct end",
+        COMPLETION_STRUCTURE, structure
+    );
+    let out = match querier.query(&query) {
+        Some(out) => out,
+        None => return vec![],
+    };
+    signature_members(&out)
+        .into_iter()
+        .filter(|member| member.starts_with(prefix))
+        .map(|member| format!("{}.{}", structure, member))
+        .collect()
+}
+
+fn is_identifier(word: &str) -> bool {
+    word.starts_with(char::is_alphabetic)
+        && word
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '\'')
+}
+
+const SIGNATURE_KEYWORDS: &[&str] = &[
+    "val",
+    "exception",
+    "structure",
+    "type",
+    "eqtype",
+    "datatype",
+];
+
+/// Names declared in a printed signature
+/// ```text
+/// structure S :
+///   sig
+///     datatype 'a option = NONE | SOME of 'a
+///     val map : ('a -> 'b) -> 'a list -> 'b list
+///   end
+/// ```
+/// only the first signature is read
+fn signature_members(signature: &str) -> Vec<String> {
+    let mut members = vec![];
+    for line in signature.lines().skip(1) {
+        if line.trim_end() == "  end" {
+            break;
+        }
+        let mut words = line.split_whitespace();
+        let keyword = words.next().unwrap_or_default();
+        if !SIGNATURE_KEYWORDS.contains(&keyword) {
+            continue;
+        }
+        // skip type variables: `'a` `('a,'b)`
+        let name = words.find(|w| !w.starts_with('\'') && !w.starts_with('('));
+        let name = match name {
+            Some(name) => name.trim_end_matches(':'),
+            None => continue,
         };
-        // auto insert ;
-        inp.insert(inp.len() - 1, ';');
+        members.push(name.to_owned());
+
+        // datatype constructors are members too
+        if keyword == "datatype" {
+            if let Some(eq) = line.find(" = ") {
+                for constructor in line[eq + 3..].split('|') {
+                    if let Some(constructor) = constructor.split_whitespace().next() {
+                        members.push(constructor.to_owned());
+                    }
+                }
+            }
+        }
+    }
+    members
+}
 
-        process
-            .stdin
-            .as_mut()
-            .unwrap()
-            .write_all(inp.as_bytes())
-            .unwrap();
+pub fn highlight(line: &str, pos: usize) -> String {
+    let tokens = lexer::lex(line);
+    highlight::paint(line, &tokens, Some(pos), |token| style(line, token))
+}
+
+/// Lines printed for new bindings, the rest of the output is left alone
+const BINDINGS: &[&str] = &[
+    "val",
+    "fun",
+    "datatype",
+    "type",
+    "eqtype",
+    "exception",
+    "structure",
+    "signature",
+    "functor",
+];
 
-        read(&mut out, &mut buf);
+/// Constructors printed like constants
+const CONSTANTS: &[&str] = &["true", "false", "nil", "NONE", "LESS", "EQUAL", "GREATER"];
 
-        // remove "- "
-        let out = String::from_utf8(out[..out.len() - 2].to_vec()).unwrap();
-        let err: String = rx_err.try_iter().collect();
-        tx_out.send(out + &err).unwrap();
+/// `val it = [1,2] : int list`, the value and the type are colored apart
+pub fn highlight_output(out: &str) -> String {
+    if highlight::is_colored(out) {
+        return out.to_owned();
     }
+    let tokens = lexer::lex(out);
+    let roles = output_roles(out, &tokens);
+    highlight::paint(out, &tokens, None, |token| roles.get(&token.start).copied())
 }
 
-pub fn highlight(line: &str, _pos: usize) -> String {
-    // these chars can't be replaced \x1b[;3m0
-    line.replace("exception", &"exception".red())
-        .replace("datatype", &"datatype".green())
-        //sig + signature
-        .replace("sig", &"sig".green())
-        .replace("nature", &"nature".green())
-        //struct + structure
-        .replace("struct", &"struct".green())
-        .replace("type", &"type".green())
-        .replace("raise", &"raise".red())
-        .replace("case", &"case".light_blue())
-        .replace("then", &"then".light_blue())
-        .replace("else", &"else".light_blue())
-        .replace("ure", &"ure".green())
-        .replace("val", &"val".green())
-        .replace("end", &"end".green())
-        .replace("fun", &"fun".green())
-        .replace("of", &"of".light_blue())
-        .replace("if", &"if".light_blue())
-        .replace("|", &"|".light_blue())
-        .replace("(", &"(".yellow())
-        .replace("*", &"*".yellow())
-        .replace(")", &")".yellow())
-        .replace(":", &":".yellow())
-        .replace("-", &"-".yellow())
-        .replace("~", &"~".yellow())
-        .replace("+", &"+".yellow())
-        .replace(">", &">".yellow())
-        .replace("<", &"<".yellow())
-        .replace("/", &"/".yellow())
-        .replace("=", &"=".yellow())
-        .replace(",", &",".yellow())
-        .replace(".", &".".red())
+/// Roles by token start, a binding line and its indented continuation lines
+/// are split at the first `:` outside of brackets
+fn output_roles(out: &str, tokens: &[Token]) -> HashMap<usize, Role> {
+    let mut roles = HashMap::new();
+    let mut line_start = true;
+    let mut binding = false;
+    let mut in_type = false;
+    let mut depth = 0;
+    for token in tokens {
+        let text = token.text(out);
+        if token.class == Class::Whitespace {
+            // a long value goes on on indented lines
+            if let Some(newline) = text.rfind('\n') {
+                line_start = newline == text.len() - 1;
+            }
+            continue;
+        }
+        if line_start {
+            line_start = false;
+            binding = BINDINGS.contains(&text);
+            in_type = false;
+            depth = 0;
+        }
+        if !binding {
+            continue;
+        }
+        match token.class {
+            Class::OpenBracket => depth += 1,
+            Class::CloseBracket => depth -= 1,
+            _ => (),
+        }
+        let role = if in_type {
+            match token.class {
+                Class::Identifier | Class::Type => Role::Type,
+                Class::Keyword | Class::Operator => Role::Operator,
+                _ => continue,
+            }
+        } else {
+            match token.class {
+                Class::Keyword if text == ":" && depth == 0 => {
+                    in_type = true;
+                    Role::Operator
+                }
+                Class::Keyword => Role::Keyword,
+                Class::Identifier if CONSTANTS.contains(&text) => Role::Constant,
+                Class::String => Role::String,
+                Class::Char => Role::Char,
+                Class::Number => Role::Number,
+                _ => continue,
+            }
+        };
+        roles.insert(token.start, role);
+    }
+    roles
+}
+
+fn style(src: &str, token: &Token) -> Option<Role> {
+    let text = token.text(src);
+    let role = match token.class {
+        Class::Keyword => match text {
+            "exception" | "raise" | "handle" => Role::Special,
+            "case" | "of" | "if" | "then" | "else" | "|" => Role::Conditional,
+            "=" | "=>" | "->" | ":" | ":>" => Role::Operator,
+            _ => Role::Keyword,
+        },
+        Class::Operator => Role::Operator,
+        Class::Punctuation if text == "," || text == ";" => Role::Punctuation,
+        Class::Punctuation if text == "." => Role::Special,
+        Class::Type => Role::Type,
+        Class::String => Role::String,
+        Class::Char => Role::Char,
+        Class::Number => Role::Number,
+        Class::Comment => Role::Comment,
+        _ => return None,
+    };
+    Some(role)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::{is_incomplete, is_valid};
+
+    #[test]
+    fn open_blocks_are_incomplete() {
+        assert!(is_incomplete(validate("val x = let val y = 1 in y")));
+        assert!(is_incomplete(validate("structure S = struct val x = 1")));
+        assert!(is_incomplete(validate("signature S = sig val x : int")));
+        assert!(is_incomplete(validate("local val x = 1 in val y = x")));
+        assert!(is_incomplete(validate("val x = (1 +")));
+        assert!(is_incomplete(validate("(* a (* b *) c")));
+        assert!(is_incomplete(validate("fun f x =")));
+    }
+
+    #[test]
+    fn closed_declarations_are_valid() {
+        assert!(is_valid(validate("val x = let val y = 1 in y end")));
+        assert!(is_valid(validate("structure S = struct val x = 1 end")));
+        assert!(is_valid(validate("val s = \"let (\"")));
+        assert!(is_valid(validate("val x = 1 (* let *)")));
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(matches!(
+            validate("val x = 1 end"),
+            ValidationResult::Invalid(_)
+        ));
+        assert!(matches!(
+            validate("val x = (1]"),
+            ValidationResult::Invalid(_)
+        ));
+        assert!(matches!(
+            validate("val s = \"abc"),
+            ValidationResult::Invalid(_)
+        ));
+    }
+
+    #[test]
+    fn semicolon_is_added_once() {
+        assert_eq!(prepare("val x = 1"), "val x = 1;\n");
+        assert_eq!(prepare("val x = 1;"), "val x = 1;\n");
+        assert_eq!(prepare("val x = 1; (* done *)"), "val x = 1; (* done *)\n");
+        assert_eq!(prepare("fun f x =\n  x + 1"), "fun f x =   x + 1;\n");
+    }
+
+    #[test]
+    fn signature_members_of_a_structure() {
+        let out = "\
+structure IHskCompletion__ :
+  sig
+    datatype 'a option = NONE | SOME of 'a
+    exception Option
+    val getOpt : 'a option * 'a -> 'a
+    val isSome : 'a option -> bool
+    val compose : ('a -> 'b) * ('c -> 'b option) -> 'c -> 'b option
+    type ('a,'b) pair = 'a * 'b
+    eqtype t
+    structure Inner : sig val x : int end
+  end
+structure IHskCompletion__ : sig end
+";
+        assert_eq!(
+            signature_members(out),
+            [
+                "option", "NONE", "SOME", "Option", "getOpt", "isSome", "compose", "pair", "t",
+                "Inner"
+            ]
+        );
+    }
+
+    /// The tokens given a role, in order
+    fn roles(out: &str) -> Vec<(&str, Role)> {
+        let tokens = lexer::lex(out);
+        let roles = output_roles(out, &tokens);
+        tokens
+            .iter()
+            .filter_map(|token| Some((token.text(out), *roles.get(&token.start)?)))
+            .collect()
+    }
+
+    #[test]
+    fn bindings_split_value_and_type() {
+        assert_eq!(
+            roles("val it = (1,\"a\") : int * string"),
+            [
+                ("val", Role::Keyword),
+                ("=", Role::Keyword),
+                ("1", Role::Number),
+                ("\"a\"", Role::String),
+                (":", Role::Operator),
+                ("int", Role::Type),
+                ("*", Role::Operator),
+                ("string", Role::Type),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_values_go_on_over_indented_lines() {
+        let out = "val xs = [true,false,\n   NONE] : bool option list\nhello\n";
+        let roles = roles(out);
+        assert!(roles.contains(&("NONE", Role::Constant)));
+        assert!(roles.contains(&("list", Role::Type)));
+        // printed by the program, not a binding
+        assert!(!roles.iter().any(|(text, _)| *text == "hello"));
+    }
 }
@@ -0,0 +1,99 @@
+use crate::diagnostic::{Diagnostic, Severity, Span};
+
+/// Splits smlnj output into its `stdIn:1.2-1.6 Error: ..` blocks and the rest
+/// the input was sent joined on one line, columns are mapped back onto the lines of `source`
+pub fn parse(out: &str, source: &str) -> (Vec<Diagnostic>, String) {
+    let mut diagnostics = vec![];
+    let mut rest = String::new();
+    let lines: Vec<&str> = out.lines().collect();
+    let mut idx = 0;
+    while idx < lines.len() {
+        let line = lines[idx];
+        idx += 1;
+        let (severity, span, head) = match header(line) {
+            Some(header) => header,
+            None => {
+                rest.push_str(line);
+                rest.push('\n');
+                continue;
+            }
+        };
+        // `  operator domain: int * int` lines
+        let mut message = head.to_owned();
+        while idx < lines.len() && lines[idx].starts_with(' ') {
+            message.push_str("\n    ");
+            message.push_str(lines[idx].strip_prefix("  ").unwrap_or(lines[idx]));
+            idx += 1;
+        }
+        diagnostics.push(Diagnostic {
+            severity,
+            span: span.map(|span| map_span(span, source)),
+            message,
+            suggestions: vec![],
+        });
+    }
+    (diagnostics, rest)
+}
+
+/// A backend line and column range, the end column is excluded
+type RawSpan = ((usize, usize), (usize, usize));
+
+/// `stdIn:1.2-1.6 Error: msg` `stdIn:3.5 Warning: msg`
+fn header(line: &str) -> Option<(Severity, Option<RawSpan>, &str)> {
+    let rest = line.strip_prefix("stdIn:")?;
+    let (position, rest) = rest.split_at(rest.find(' ')?);
+    let rest = rest.trim_start();
+    let (severity, head) = if let Some(head) = rest.strip_prefix("Error:") {
+        (Severity::Error, head)
+    } else if let Some(head) = rest.strip_prefix("Warning:") {
+        (Severity::Warning, head)
+    } else {
+        return None;
+    };
+    Some((severity, parse_position(position), head.trim()))
+}
+
+/// `1.2-1.6` `1.2`
+fn parse_position(position: &str) -> Option<RawSpan> {
+    let point = |s: &str| {
+        let dot = s.find('.')?;
+        Some((s[..dot].parse().ok()?, s[dot + 1..].parse().ok()?))
+    };
+    match position.find('-') {
+        Some(dash) => Some((point(&position[..dash])?, point(&position[dash + 1..])?)),
+        None => {
+            let (line, col) = point(position)?;
+            Some(((line, col), (line, col + 1)))
+        }
+    }
+}
+
+/// smlnj numbers lines over the whole session, all of the input is on the start line
+fn map_span((start, end): RawSpan, source: &str) -> Span {
+    let lines: Vec<&str> = source.lines().collect();
+    let last = match lines.last() {
+        Some(last) => (lines.len(), last.chars().count()),
+        None => (1, 1),
+    };
+    let end = if end.0 == start.0 {
+        unjoin(&lines, end.1.saturating_sub(1).max(start.1))
+    } else {
+        last
+    };
+    Span {
+        start: unjoin(&lines, start.1),
+        end,
+    }
+}
+
+/// Column of the joined line to a line and column of the lines joined with a space
+fn unjoin(lines: &[&str], mut col: usize) -> (usize, usize) {
+    for (idx, line) in lines.iter().enumerate() {
+        let len = line.chars().count();
+        if col <= len || idx == lines.len() - 1 {
+            return (idx + 1, col.max(1));
+        }
+        col -= len + 1;
+    }
+    (1, col.max(1))
+}
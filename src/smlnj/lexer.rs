@@ -0,0 +1,291 @@
+use crate::token::{Class, Cursor, Token};
+
+pub const KEYWORDS: &[&str] = &[
+    "abstype",
+    "and",
+    "andalso",
+    "as",
+    "case",
+    "datatype",
+    "do",
+    "else",
+    "end",
+    "eqtype",
+    "exception",
+    "fn",
+    "fun",
+    "functor",
+    "handle",
+    "if",
+    "in",
+    "include",
+    "infix",
+    "infixr",
+    "let",
+    "local",
+    "nonfix",
+    "of",
+    "op",
+    "open",
+    "orelse",
+    "raise",
+    "rec",
+    "sharing",
+    "sig",
+    "signature",
+    "struct",
+    "structure",
+    "then",
+    "type",
+    "val",
+    "where",
+    "while",
+    "with",
+    "withtype",
+];
+
+fn is_symbol(c: char) -> bool {
+    "!%&$#+-/:<=>?@\\~`^|*".contains(c)
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\''
+}
+
+/// Splits sml source into tokens, the tokens cover the whole input
+pub fn lex(src: &str) -> Vec<Token> {
+    let mut cursor = Cursor::new(src);
+    let mut tokens = vec![];
+    while let Some(c) = cursor.peek() {
+        let start = cursor.pos();
+        let token = if c.is_whitespace() {
+            cursor.eat_while(char::is_whitespace);
+            cursor.token(Class::Whitespace, start)
+        } else if cursor.starts_with("(*") {
+            comment(&mut cursor)
+        } else if c == '"' {
+            string(&mut cursor, Class::String)
+        } else if cursor.starts_with("#\"") {
+            cursor.bump();
+            string(&mut cursor, Class::Char)
+        } else if c == '\'' {
+            // type variables `'a` `''eq`
+            cursor.eat_while(is_ident_char);
+            cursor.token(Class::Type, start)
+        } else if c.is_alphabetic() {
+            long_identifier(&mut cursor, &mut tokens)
+        } else if c.is_ascii_digit()
+            || (c == '~' && matches!(cursor.peek_nth(1), Some(d) if d.is_ascii_digit()))
+        {
+            number(&mut cursor)
+        } else if "([{".contains(c) {
+            cursor.bump();
+            cursor.token(Class::OpenBracket, start)
+        } else if ")]}".contains(c) {
+            cursor.bump();
+            cursor.token(Class::CloseBracket, start)
+        } else if is_symbol(c) {
+            cursor.eat_while(is_symbol);
+            cursor.token(Class::Operator, start)
+        } else {
+            cursor.bump();
+            cursor.token(Class::Punctuation, start)
+        };
+        tokens.push(token);
+    }
+    tokens.iter_mut().for_each(|token| {
+        // `=` `|` `=>` `->` `:` are reserved
+        if token.class == Class::Operator
+            && ["=", "|", "=>", "->", ":", ":>"].contains(&&src[token.start..token.end])
+        {
+            token.class = Class::Keyword;
+        }
+    });
+    tokens
+}
+
+/// `x` `List.map` `Int.+`
+/// the structure path is a type token followed by the name token
+fn long_identifier(cursor: &mut Cursor, tokens: &mut Vec<Token>) -> Token {
+    let start = cursor.pos();
+    cursor.eat_while(is_ident_char);
+    while cursor.peek() == Some('.')
+        && matches!(cursor.peek_nth(1), Some(c) if c.is_alphabetic() || is_symbol(c))
+        && !KEYWORDS.contains(&cursor.since(start))
+    {
+        cursor.skip(1);
+        let name_start = cursor.pos();
+        let class = if matches!(cursor.peek(), Some(c) if is_symbol(c)) {
+            cursor.eat_while(is_symbol);
+            Class::Operator
+        } else {
+            cursor.eat_while(is_ident_char);
+            if cursor.peek() == Some('.') {
+                // still on the structure path
+                continue;
+            }
+            Class::Identifier
+        };
+        tokens.push(Token {
+            end: name_start,
+            ..cursor.token(Class::Type, start)
+        });
+        return cursor.token(class, name_start);
+    }
+    let class = if KEYWORDS.contains(&cursor.since(start)) {
+        Class::Keyword
+    } else {
+        Class::Identifier
+    };
+    cursor.token(class, start)
+}
+
+/// `42` `~3` `0wx1F` `4.2e~1`
+fn number(cursor: &mut Cursor) -> Token {
+    let start = cursor.pos();
+    if cursor.peek() == Some('~') {
+        cursor.bump();
+    }
+    cursor.eat_while(|c| c.is_ascii_alphanumeric());
+    if cursor.peek() == Some('.') && matches!(cursor.peek_nth(1), Some(c) if c.is_ascii_digit()) {
+        cursor.skip(1);
+        cursor.eat_while(|c| c.is_ascii_alphanumeric());
+    }
+    let number = cursor.since(start);
+    let exponent = (number.ends_with('e') || number.ends_with('E'))
+        && cursor.peek() == Some('~')
+        && matches!(cursor.peek_nth(1), Some(c) if c.is_ascii_digit());
+    if exponent {
+        cursor.skip(1);
+        cursor.eat_while(|c| c.is_ascii_digit());
+    }
+    cursor.token(Class::Number, start)
+}
+
+/// `(* *)` comments nest
+fn comment(cursor: &mut Cursor) -> Token {
+    let start = cursor.pos();
+    let mut depth = 0;
+    while !cursor.is_eof() {
+        if cursor.starts_with("(*") {
+            depth += 1;
+            cursor.skip(2);
+        } else if cursor.starts_with("*)") {
+            depth -= 1;
+            cursor.skip(2);
+            if depth == 0 {
+                return cursor.token(Class::Comment, start);
+            }
+        } else {
+            cursor.bump();
+        }
+    }
+    Token {
+        closed: false,
+        ..cursor.token(Class::Comment, start)
+    }
+}
+
+/// `"..."` strings and `#"c"` chars, starting at the quote
+fn string(cursor: &mut Cursor, class: Class) -> Token {
+    // `#` was already consumed for chars
+    let start = if class == Class::Char {
+        cursor.pos() - 1
+    } else {
+        cursor.pos()
+    };
+    cursor.bump();
+    while let Some(c) = cursor.bump() {
+        match c {
+            '"' => return cursor.token(class, start),
+            // escapes and gaps `\   \`
+            '\\' => {
+                if matches!(cursor.peek(), Some(c) if c.is_whitespace()) {
+                    cursor.eat_while(char::is_whitespace);
+                    if cursor.peek() == Some('\\') {
+                        cursor.bump();
+                    }
+                } else {
+                    cursor.bump();
+                }
+            }
+            '\n' => break,
+            _ => (),
+        }
+    }
+    Token {
+        closed: false,
+        ..cursor.token(class, start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tokens that aren't whitespace
+    fn tokens(src: &str) -> Vec<(Class, &str)> {
+        lex(src)
+            .into_iter()
+            .filter(|token| token.class != Class::Whitespace)
+            .map(|token| (token.class, token.text(src)))
+            .collect()
+    }
+
+    #[test]
+    fn keywords_are_whole_words() {
+        assert_eq!(
+            tokens("structure pure = struct end"),
+            vec![
+                (Class::Keyword, "structure"),
+                (Class::Identifier, "pure"),
+                (Class::Keyword, "="),
+                (Class::Keyword, "struct"),
+                (Class::Keyword, "end"),
+            ]
+        );
+        assert_eq!(tokens("ending"), vec![(Class::Identifier, "ending")]);
+    }
+
+    #[test]
+    fn type_variables_and_negative_numbers() {
+        assert_eq!(
+            tokens("'a list ~3"),
+            vec![
+                (Class::Type, "'a"),
+                (Class::Identifier, "list"),
+                (Class::Number, "~3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_comments() {
+        let src = "(* a (* b *) c *) x";
+        assert_eq!(
+            tokens(src),
+            vec![
+                (Class::Comment, "(* a (* b *) c *)"),
+                (Class::Identifier, "x")
+            ]
+        );
+        assert!(!lex("(* a (* b *)")[0].closed);
+    }
+
+    #[test]
+    fn strings_and_chars() {
+        assert_eq!(
+            tokens(r#""a \" (" #"c""#),
+            vec![(Class::String, r#""a \" (""#), (Class::Char, r##"#"c""##)]
+        );
+        assert!(!lex("\"abc")[0].closed);
+    }
+
+    #[test]
+    fn long_identifiers() {
+        assert_eq!(
+            tokens("List.map"),
+            vec![(Class::Type, "List."), (Class::Identifier, "map")]
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant};
+
+/// What the repl process used so far, from `/proc/<pid>`
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    /// user and system time
+    cpu: Duration,
+    /// `VmHWM`, the peak resident set size in kB
+    peak_rss: u64,
+}
+
+impl Usage {
+    fn of(pid: u32) -> Option<Self> {
+        Some(Self {
+            cpu: cpu_time(pid)?,
+            peak_rss: peak_rss(pid)?,
+        })
+    }
+}
+
+/// `utime` and `stime` of `/proc/<pid>/stat`, in clock ticks
+fn cpu_time(pid: u32) -> Option<Duration> {
+    use nix::unistd::{sysconf, SysconfVar};
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let ticks = sysconf(SysconfVar::CLK_TCK).ok()??;
+    parse_cpu_time(&stat, ticks as u64)
+}
+
+fn parse_cpu_time(stat: &str, ticks: u64) -> Option<Duration> {
+    // the command name is in parentheses and can hold spaces
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+    // utime is the 14th field, the 12th after the name
+    let utime: u64 = fields.nth(11)?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some(Duration::from_millis((utime + stime) * 1000 / ticks))
+}
+
+/// `VmHWM:    12345 kB` of `/proc/<pid>/status`
+fn peak_rss(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    parse_peak_rss(&status)
+}
+
+fn parse_peak_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line["VmHWM:".len()..]
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Taken before sending the input
+pub struct Sample {
+    at: Instant,
+    usage: Option<Usage>,
+}
+
+impl Sample {
+    pub fn take(pid: u32) -> Self {
+        Self {
+            at: Instant::now(),
+            usage: Usage::of(pid),
+        }
+    }
+
+    /// What happened since the sample, the cpu and memory are missing without `/proc`
+    pub fn stats(&self, pid: u32) -> Stats {
+        let wall = self.at.elapsed();
+        let (cpu, peak_rss) = match (self.usage, Usage::of(pid)) {
+            (Some(before), Some(after)) => (
+                Some(after.cpu.saturating_sub(before.cpu)),
+                Some(after.peak_rss.saturating_sub(before.peak_rss)),
+            ),
+            _ => (None, None),
+        };
+        Stats {
+            wall,
+            cpu,
+            peak_rss,
+        }
+    }
+}
+
+/// What one evaluation cost
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub wall: Duration,
+    pub cpu: Option<Duration>,
+    /// growth of the peak resident set size in kB, 0 when the old peak wasn't passed
+    pub peak_rss: Option<u64>,
+}
+
+impl Stats {
+    /// `wall 1.20s  cpu 1.15s  peak rss +12.5MB`
+    pub fn footer(&self) -> String {
+        let mut footer = format!("wall {}", duration(self.wall));
+        if let Some(cpu) = self.cpu {
+            footer.push_str(&format!("  cpu {}", duration(cpu)));
+        }
+        if let Some(peak_rss) = self.peak_rss {
+            footer.push_str(&format!("  peak rss +{}", size(peak_rss)));
+        }
+        footer
+    }
+}
+
+fn duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{}ms", duration.as_millis())
+    } else {
+        format!("{:.2}s", duration.as_secs_f64())
+    }
+}
+
+fn size(kb: u64) -> String {
+    if kb < 1024 {
+        format!("{}kB", kb)
+    } else {
+        format!("{:.1}MB", kb as f64 / 1024.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proc_files_are_parsed() {
+        let stat = "4242 (ghc (iserv) 1) S 1 4242 4242 0 -1 4194304 900 0 0 0 150 25 0 0 20 0";
+        assert_eq!(parse_cpu_time(stat, 100), Some(Duration::from_millis(1750)));
+        assert_eq!(parse_cpu_time("4242 (ghc) S 1", 100), None);
+        let status = "Name:\tghc\nVmPeak:\t  99 kB\nVmHWM:\t   12345 kB\nVmRSS:\t 100 kB\n";
+        assert_eq!(parse_peak_rss(status), Some(12345));
+        assert_eq!(parse_peak_rss("Name:\tghc\n"), None);
+    }
+
+    #[test]
+    fn footer_shows_what_is_known() {
+        let stats = Stats {
+            wall: Duration::from_millis(1200),
+            cpu: Some(Duration::from_millis(40)),
+            peak_rss: Some(12800),
+        };
+        assert_eq!(stats.footer(), "wall 1.20s  cpu 40ms  peak rss +12.5MB");
+        let stats = Stats {
+            wall: Duration::from_millis(3),
+            cpu: None,
+            peak_rss: None,
+        };
+        assert_eq!(stats.footer(), "wall 3ms");
+    }
+}
@@ -0,0 +1,259 @@
+use once_cell::sync::Lazy;
+use std::os::unix::io::AsRawFd;
+
+/// What the terminal can show, from least to most
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Colors {
+    /// no escape sequences at all, for logs, pipes and screen readers
+    Plain,
+    /// bold, underline.. but no colors, `NO_COLOR`
+    Attributes,
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+/// Forced with `IHSK_COLOR=plain|nocolor|16|256|truecolor`, detected otherwise
+pub static COLORS: Lazy<Colors> = Lazy::new(|| {
+    let is_tty = nix::unistd::isatty(std::io::stdout().as_raw_fd()).unwrap_or(false);
+    detect(|name| std::env::var(name).ok(), is_tty)
+});
+
+/// The colors from the environment `var` and whether stdout is a terminal
+fn detect(var: impl Fn(&str) -> Option<String>, is_tty: bool) -> Colors {
+    match var("IHSK_COLOR").as_deref() {
+        Some("plain") => return Colors::Plain,
+        Some("nocolor") => return Colors::Attributes,
+        Some("16") => return Colors::Ansi16,
+        Some("256") => return Colors::Ansi256,
+        Some("truecolor") => return Colors::TrueColor,
+        _ => (),
+    }
+    if !is_tty {
+        return Colors::Plain;
+    }
+    let term = var("TERM").unwrap_or_default();
+    if term.is_empty() || term == "dumb" {
+        return Colors::Plain;
+    }
+    if matches!(var("NO_COLOR"), Some(no_color) if !no_color.is_empty()) {
+        return Colors::Attributes;
+    }
+    match var("COLORTERM").as_deref() {
+        Some("truecolor") | Some("24bit") => Colors::TrueColor,
+        _ if term.contains("256color") => Colors::Ansi256,
+        _ => Colors::Ansi16,
+    }
+}
+
+nix::ioctl_read_bad!(window_size, nix::libc::TIOCGWINSZ, nix::pty::Winsize);
+
+/// Rows of the terminal, None when stdout isn't one
+pub fn height() -> Option<usize> {
+    let mut size = nix::pty::Winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    unsafe { window_size(std::io::stdout().as_raw_fd(), &mut size) }.ok()?;
+    match size.ws_row {
+        0 => None,
+        rows => Some(rows as usize),
+    }
+}
+
+/// The 16 ansi colors as xterm shows them
+const ANSI16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// Steps of the 6x6x6 color cube of the 256 palette
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Rewrites an SGR style (`1;38;2;255;100;0`) with what `colors` can show
+/// None if nothing is left
+pub fn downgrade(sgr: &str, colors: Colors) -> Option<String> {
+    if colors == Colors::Plain {
+        return None;
+    }
+    let params: Vec<&str> = sgr.split(';').collect();
+    let mut out: Vec<String> = vec![];
+    let mut idx = 0;
+    while idx < params.len() {
+        let param = params[idx];
+        idx += 1;
+        let code: u8 = match param.parse() {
+            Ok(code) => code,
+            Err(_) => continue,
+        };
+        // `38;5;n` `38;2;r;g;b` and the same for backgrounds with 48
+        let extended = match (code, params.get(idx).copied()) {
+            (38, Some("5")) | (48, Some("5")) => {
+                let n = params.get(idx + 1).and_then(|n| n.parse().ok());
+                idx += 2;
+                n.map(|n| (code == 48, rgb_of_256(n), Some(n)))
+            }
+            (38, Some("2")) | (48, Some("2")) => {
+                let channel = |i: usize| params.get(idx + i).and_then(|c| c.parse::<u8>().ok());
+                let rgb = (channel(1), channel(2), channel(3));
+                idx += 4;
+                match rgb {
+                    (Some(r), Some(g), Some(b)) => Some((code == 48, (r, g, b), None)),
+                    _ => None,
+                }
+            }
+            _ => {
+                let is_color = matches!(code, 30..=39 | 40..=49 | 90..=97 | 100..=107);
+                if !is_color || colors != Colors::Attributes {
+                    out.push(param.to_owned());
+                }
+                continue;
+            }
+        };
+        let (background, rgb, index) = match extended {
+            Some(extended) => extended,
+            None => continue,
+        };
+        let layer = if background { 48 } else { 38 };
+        match colors {
+            Colors::TrueColor => match index {
+                Some(n) => out.push(format!("{};5;{}", layer, n)),
+                None => out.push(format!("{};2;{};{};{}", layer, rgb.0, rgb.1, rgb.2)),
+            },
+            Colors::Ansi256 => {
+                let n = index.unwrap_or_else(|| index_256(rgb));
+                out.push(format!("{};5;{}", layer, n));
+            }
+            Colors::Ansi16 => {
+                let n = nearest_16(rgb);
+                let base = match (background, n < 8) {
+                    (false, true) => 30,
+                    (false, false) => 90 - 8,
+                    (true, true) => 40,
+                    (true, false) => 100 - 8,
+                };
+                out.push((base + n).to_string());
+            }
+            Colors::Attributes | Colors::Plain => (),
+        }
+    }
+    if out.is_empty() {
+        None
+    } else {
+        Some(out.join(";"))
+    }
+}
+
+fn rgb_of_256(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => ANSI16[n as usize],
+        16..=231 => {
+            let n = n - 16;
+            (
+                CUBE[(n / 36) as usize],
+                CUBE[(n / 6 % 6) as usize],
+                CUBE[(n % 6) as usize],
+            )
+        }
+        _ => {
+            let gray = 8 + 10 * (n - 232);
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// Closest entry of the color cube
+fn index_256((r, g, b): (u8, u8, u8)) -> u8 {
+    let step = |c: u8| {
+        CUBE.iter()
+            .enumerate()
+            .min_by_key(|(_, level)| (i32::from(**level) - i32::from(c)).abs())
+            .map_or(0, |(idx, _)| idx as u8)
+    };
+    16 + 36 * step(r) + 6 * step(g) + step(b)
+}
+
+fn nearest_16((r, g, b): (u8, u8, u8)) -> u8 {
+    let distance = |(r2, g2, b2): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).pow(2);
+        d(r, r2) + d(g, g2) + d(b, b2)
+    };
+    (0..16u8)
+        .min_by_key(|&n| distance(ANSI16[n as usize]))
+        .unwrap_or(7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colors(vars: &[(&str, &str)], is_tty: bool) -> Colors {
+        let var = |name: &str| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        };
+        detect(var, is_tty)
+    }
+
+    #[test]
+    fn colors_follow_the_environment() {
+        let term = ("TERM", "xterm-256color");
+        assert_eq!(colors(&[term], true), Colors::Ansi256);
+        assert_eq!(colors(&[("TERM", "xterm")], true), Colors::Ansi16);
+        assert_eq!(
+            colors(&[term, ("COLORTERM", "truecolor")], true),
+            Colors::TrueColor
+        );
+        assert_eq!(colors(&[term, ("NO_COLOR", "1")], true), Colors::Attributes);
+        assert_eq!(colors(&[term, ("NO_COLOR", "")], true), Colors::Ansi256);
+        assert_eq!(colors(&[("TERM", "dumb")], true), Colors::Plain);
+        assert_eq!(colors(&[], true), Colors::Plain);
+        assert_eq!(colors(&[term], false), Colors::Plain);
+        assert_eq!(colors(&[("IHSK_COLOR", "16")], false), Colors::Ansi16);
+        assert_eq!(
+            colors(&[term, ("IHSK_COLOR", "plain")], true),
+            Colors::Plain
+        );
+    }
+
+    #[test]
+    fn downgrade_keeps_what_the_terminal_shows() {
+        assert_eq!(
+            downgrade("1;38;2;255;0;0", Colors::TrueColor).as_deref(),
+            Some("1;38;2;255;0;0")
+        );
+        assert_eq!(
+            downgrade("1;38;2;255;0;0", Colors::Ansi256).as_deref(),
+            Some("1;38;5;196")
+        );
+        assert_eq!(
+            downgrade("1;38;2;255;0;0", Colors::Ansi16).as_deref(),
+            Some("1;91")
+        );
+        assert_eq!(downgrade("38;5;196", Colors::Ansi16).as_deref(), Some("91"));
+        assert_eq!(
+            downgrade("1;31;44", Colors::Attributes).as_deref(),
+            Some("1")
+        );
+        assert_eq!(downgrade("38;5;25", Colors::Attributes), None);
+        assert_eq!(downgrade("1", Colors::Plain), None);
+        assert_eq!(downgrade("0", Colors::Ansi16).as_deref(), Some("0"));
+    }
+}
@@ -0,0 +1,298 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+
+use crate::term::{self, Colors};
+
+/// What a piece of input or decoration is, the theme gives each one a style
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    Keyword,
+    /// `data` `const` `=>`..
+    Declaration,
+    /// `case` `if`..
+    Conditional,
+    /// `where` `raise` `set!` `$`..
+    Special,
+    /// well known globals
+    Builtin,
+    /// `true` `null` `'sym` `#:kw`..
+    Constant,
+    /// record fields and object keys in the output
+    Field,
+    Type,
+    Operator,
+    Punctuation,
+    String,
+    Char,
+    Number,
+    Regex,
+    Comment,
+    Hint,
+    Error,
+    Warning,
+    /// a closing bracket with no opener or the wrong one
+    Unmatched,
+    /// added to the bracket at the cursor and its partner
+    CursorBracket,
+    PromptIn,
+    PromptOut,
+}
+
+const ROLE_NAMES: &[(&str, Role)] = &[
+    ("keyword", Role::Keyword),
+    ("declaration", Role::Declaration),
+    ("conditional", Role::Conditional),
+    ("special", Role::Special),
+    ("builtin", Role::Builtin),
+    ("constant", Role::Constant),
+    ("field", Role::Field),
+    ("type", Role::Type),
+    ("operator", Role::Operator),
+    ("punctuation", Role::Punctuation),
+    ("string", Role::String),
+    ("char", Role::Char),
+    ("number", Role::Number),
+    ("regex", Role::Regex),
+    ("comment", Role::Comment),
+    ("hint", Role::Hint),
+    ("error", Role::Error),
+    ("warning", Role::Warning),
+    ("unmatched", Role::Unmatched),
+    ("cursor_bracket", Role::CursorBracket),
+    ("prompt_in", Role::PromptIn),
+    ("prompt_out", Role::PromptOut),
+];
+
+/// Bracket colors cycling by nesting depth
+/// every color keeps a 3:1 contrast ratio on both black and white backgrounds
+const RAINBOW: &[&str] = &[
+    "38;5;166", "38;5;32", "38;5;127", "38;5;28", "38;5;99", "38;5;136",
+];
+
+const DARK: &[(Role, &str)] = &[
+    (Role::Keyword, "1;32"),
+    (Role::Declaration, "1;34"),
+    (Role::Conditional, "1;36"),
+    (Role::Special, "1;31"),
+    (Role::Builtin, "94"),
+    (Role::Constant, "36"),
+    (Role::Field, "34"),
+    (Role::Type, "35"),
+    (Role::Operator, "33"),
+    (Role::Punctuation, "1;33"),
+    (Role::String, "32"),
+    (Role::Char, "32"),
+    (Role::Number, "36"),
+    (Role::Regex, "31"),
+    (Role::Comment, "90"),
+    (Role::Hint, "90"),
+    (Role::Error, "1;31"),
+    (Role::Warning, "1;33"),
+    (Role::Unmatched, "1;37;41"),
+    (Role::CursorBracket, "1;4"),
+    (Role::PromptIn, "1;33"),
+    (Role::PromptOut, "1;31"),
+];
+
+/// No yellow or light colors, they vanish on a white background
+const LIGHT: &[(Role, &str)] = &[
+    (Role::Keyword, "1;38;5;28"),
+    (Role::Declaration, "1;38;5;25"),
+    (Role::Conditional, "1;38;5;30"),
+    (Role::Special, "1;38;5;124"),
+    (Role::Builtin, "38;5;25"),
+    (Role::Constant, "38;5;30"),
+    (Role::Field, "38;5;25"),
+    (Role::Type, "38;5;90"),
+    (Role::Operator, "38;5;130"),
+    (Role::Punctuation, "1;38;5;130"),
+    (Role::String, "38;5;28"),
+    (Role::Char, "38;5;28"),
+    (Role::Number, "38;5;30"),
+    (Role::Regex, "38;5;124"),
+    (Role::Comment, "38;5;242"),
+    (Role::Hint, "38;5;245"),
+    (Role::Error, "1;38;5;124"),
+    (Role::Warning, "1;38;5;130"),
+    (Role::Unmatched, "1;37;41"),
+    (Role::CursorBracket, "1;4"),
+    (Role::PromptIn, "1;38;5;130"),
+    (Role::PromptOut, "1;38;5;124"),
+];
+
+/// Only bold, dim, italic and reverse
+const MONOCHROME: &[(Role, &str)] = &[
+    (Role::Keyword, "1"),
+    (Role::Declaration, "1"),
+    (Role::Conditional, "1"),
+    (Role::Special, "1"),
+    (Role::String, "3"),
+    (Role::Char, "3"),
+    (Role::Regex, "3"),
+    (Role::Comment, "2"),
+    (Role::Hint, "2"),
+    (Role::Error, "1"),
+    (Role::Warning, "1"),
+    (Role::Unmatched, "7"),
+    (Role::CursorBracket, "1;4"),
+    (Role::PromptIn, "1"),
+    (Role::PromptOut, "1"),
+];
+
+/// SGR styles (`1;33`) by role
+pub struct Theme {
+    styles: HashMap<Role, String>,
+    rainbow: Vec<String>,
+}
+
+/// Chosen with `IHSK_THEME`, dark by default
+/// and reduced to what the terminal can show
+pub static THEME: Lazy<Theme> = Lazy::new(|| {
+    let name = std::env::var("IHSK_THEME").unwrap_or_default();
+    let theme = if name.is_empty() {
+        Theme::builtin("dark").unwrap()
+    } else {
+        Theme::builtin(&name)
+            .or_else(|| Theme::user(&name))
+            .unwrap_or_else(|| {
+                eprintln!("ihsk: unknown theme `{}`, using dark", name);
+                Theme::builtin("dark").unwrap()
+            })
+    };
+    theme.downgraded(*term::COLORS)
+});
+
+impl Theme {
+    fn builtin(name: &str) -> Option<Self> {
+        let (styles, rainbow) = match name {
+            "dark" => (DARK, RAINBOW),
+            "light" => (LIGHT, RAINBOW),
+            "monochrome" => (MONOCHROME, &[][..]),
+            _ => return None,
+        };
+        Some(Self {
+            styles: styles
+                .iter()
+                .map(|(role, sgr)| (*role, (*sgr).to_owned()))
+                .collect(),
+            rainbow: rainbow.iter().map(|sgr| (*sgr).to_owned()).collect(),
+        })
+    }
+
+    /// `$XDG_CONFIG_HOME/ihsk/themes/<name>`, `key = value` lines
+    /// - `base = light` starts from a built-in theme, dark otherwise
+    /// - `keyword = 1;34` sets the style of a role, an empty value removes it
+    /// - `rainbow = 31 32 33` sets the bracket colors
+    fn user(name: &str) -> Option<Self> {
+        let path = dirs_next::config_dir()?
+            .join("ihsk")
+            .join("themes")
+            .join(name);
+        let config = std::fs::read_to_string(path).ok()?;
+        Self::parse(&config, name)
+    }
+
+    fn parse(config: &str, name: &str) -> Option<Self> {
+        let entries: Vec<(&str, &str)> = config
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let eq = line.find('=')?;
+                Some((line[..eq].trim(), line[eq + 1..].trim()))
+            })
+            .collect();
+
+        let base = entries
+            .iter()
+            .find(|(key, _)| *key == "base")
+            .map_or("dark", |(_, value)| value);
+        let mut theme = Self::builtin(base).or_else(|| Self::builtin("dark"))?;
+        for (key, value) in entries {
+            if key == "base" {
+                continue;
+            }
+            if key == "rainbow" {
+                theme.rainbow = value.split_whitespace().map(ToOwned::to_owned).collect();
+                continue;
+            }
+            match ROLE_NAMES.iter().find(|(role_name, _)| *role_name == key) {
+                Some((_, role)) => {
+                    theme.styles.insert(*role, value.to_owned());
+                }
+                None => eprintln!("ihsk: unknown key `{}` in theme `{}`", key, name),
+            }
+        }
+        Some(theme)
+    }
+
+    fn downgraded(self, colors: Colors) -> Self {
+        Self {
+            styles: self
+                .styles
+                .into_iter()
+                .filter_map(|(role, sgr)| Some((role, term::downgrade(&sgr, colors)?)))
+                .collect(),
+            rainbow: self
+                .rainbow
+                .iter()
+                .filter_map(|sgr| term::downgrade(sgr, colors))
+                .collect(),
+        }
+    }
+
+    /// None when the role is left unstyled
+    pub fn style(&self, role: Role) -> Option<&str> {
+        self.styles
+            .get(&role)
+            .map(String::as_str)
+            .filter(|sgr| !sgr.is_empty())
+    }
+
+    pub fn rainbow(&self, depth: usize) -> Option<&str> {
+        if self.rainbow.is_empty() {
+            return None;
+        }
+        Some(&self.rainbow[depth % self.rainbow.len()])
+    }
+}
+
+/// `text` in the style of `role`, unchanged if the theme leaves it unstyled
+pub fn paint(text: &str, role: Role) -> String {
+    match THEME.style(role) {
+        Some(sgr) => crate::highlight::paint_str(text, sgr),
+        None => text.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_themes_start_from_a_base() {
+        let config = "\
+# comments and blank lines are skipped
+
+base = light
+keyword = 1;34
+comment =
+rainbow = 31 32  33
+nonsense = 1
+";
+        let theme = Theme::parse(config, "mine").unwrap();
+        let light = Theme::builtin("light").unwrap();
+        assert_eq!(theme.style(Role::Keyword), Some("1;34"));
+        assert_eq!(theme.style(Role::Comment), None);
+        assert_eq!(theme.style(Role::String), light.style(Role::String));
+        assert_eq!(theme.rainbow, ["31", "32", "33"]);
+    }
+
+    #[test]
+    fn unknown_bases_fall_back_to_dark() {
+        let theme = Theme::parse("base = nope\nnot a pair", "mine").unwrap();
+        let dark = Theme::builtin("dark").unwrap();
+        assert_eq!(theme.style(Role::Keyword), dark.style(Role::Keyword));
+        assert_eq!(theme.rainbow, dark.rainbow);
+    }
+}
@@ -0,0 +1,195 @@
+use rustyline::validate::ValidationResult;
+
+/// What a piece of source is, the language lexers produce these
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Class {
+    Whitespace,
+    Comment,
+    Keyword,
+    Identifier,
+    /// type constructors, type variables..
+    Type,
+    Operator,
+    Punctuation,
+    OpenBracket,
+    CloseBracket,
+    String,
+    Char,
+    Number,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub class: Class,
+    /// byte range in the source
+    pub start: usize,
+    pub end: usize,
+    /// false for a string or comment still open at the end of the input
+    pub closed: bool,
+}
+
+impl Token {
+    pub fn text<'s>(&self, src: &'s str) -> &'s str {
+        &src[self.start..self.end]
+    }
+    pub fn is_trivia(&self) -> bool {
+        self.class == Class::Whitespace || self.class == Class::Comment
+    }
+}
+
+/// Walks a source string char by char while keeping byte offsets
+pub struct Cursor<'s> {
+    src: &'s str,
+    pos: usize,
+}
+
+impl<'s> Cursor<'s> {
+    pub fn new(src: &'s str) -> Self {
+        Self { src, pos: 0 }
+    }
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+    /// What was consumed since `start`
+    pub fn since(&self, start: usize) -> &'s str {
+        &self.src[start..self.pos]
+    }
+    pub fn rest(&self) -> &'s str {
+        &self.src[self.pos..]
+    }
+    pub fn is_eof(&self) -> bool {
+        self.pos == self.src.len()
+    }
+    pub fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+    pub fn peek_nth(&self, n: usize) -> Option<char> {
+        self.rest().chars().nth(n)
+    }
+    pub fn starts_with(&self, pat: &str) -> bool {
+        self.rest().starts_with(pat)
+    }
+    pub fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+    /// Skip `n` bytes, `n` must be on a char boundary
+    pub fn skip(&mut self, n: usize) {
+        self.pos += n;
+    }
+    pub fn eat_while(&mut self, f: impl Fn(char) -> bool) {
+        while let Some(c) = self.peek() {
+            if !f(c) {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+    }
+    /// Token of `class` from `start` to the current position
+    pub fn token(&self, class: Class, start: usize) -> Token {
+        Token {
+            class,
+            start,
+            end: self.pos,
+            closed: true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Brackets {
+    Balanced,
+    /// number of brackets still open
+    Open(usize),
+    /// byte offsets of a closing bracket and the bracket it closes (if any)
+    Mismatched {
+        open: Option<usize>,
+        close: usize,
+    },
+}
+
+pub fn matching_bracket(open: char) -> Option<char> {
+    match open {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        _ => None,
+    }
+}
+
+/// Checks the bracket tokens pair up, brackets inside strings and comments are not tokens
+pub fn check_brackets(src: &str, tokens: &[Token]) -> Brackets {
+    let mut stack = vec![];
+    for token in tokens {
+        match token.class {
+            Class::OpenBracket => stack.push(token),
+            Class::CloseBracket => {
+                let open = match stack.pop() {
+                    Some(open) => open,
+                    None => {
+                        return Brackets::Mismatched {
+                            open: None,
+                            close: token.start,
+                        }
+                    }
+                };
+                let expected = open.text(src).chars().last().and_then(matching_bracket);
+                if expected != token.text(src).chars().next() {
+                    return Brackets::Mismatched {
+                        open: Some(open.start),
+                        close: token.start,
+                    };
+                }
+            }
+            _ => (),
+        }
+    }
+    if stack.is_empty() {
+        Brackets::Balanced
+    } else {
+        Brackets::Open(stack.len())
+    }
+}
+
+/// What every validator checks first, None leaves the decision to the language rules
+/// - a token left open is incomplete if `continues` says it goes on over the next line
+///   (comments, string gaps..), else an unterminated literal
+/// - brackets still open are incomplete, mismatched ones invalid
+pub fn check_open(
+    src: &str,
+    tokens: &[Token],
+    continues: impl Fn(&Token) -> bool,
+) -> Option<ValidationResult> {
+    if let Some(token) = tokens.iter().find(|token| !token.closed) {
+        if continues(token) {
+            return Some(ValidationResult::Incomplete);
+        }
+        return Some(ValidationResult::Invalid(Some(
+            " unterminated literal".into(),
+        )));
+    }
+    match check_brackets(src, tokens) {
+        Brackets::Balanced => None,
+        Brackets::Open(_) => Some(ValidationResult::Incomplete),
+        Brackets::Mismatched { .. } => Some(ValidationResult::Invalid(Some(
+            " mismatched brackets".into(),
+        ))),
+    }
+}
+
+/// Last token that is not whitespace or a comment
+pub fn last_significant(tokens: &[Token]) -> Option<&Token> {
+    tokens.iter().rev().find(|token| !token.is_trivia())
+}
+
+/// Validator results in tests, `ValidationResult` can't be compared
+#[cfg(test)]
+pub fn is_incomplete(result: ValidationResult) -> bool {
+    matches!(result, ValidationResult::Incomplete)
+}
+
+#[cfg(test)]
+pub fn is_valid(result: ValidationResult) -> bool {
+    matches!(result, ValidationResult::Valid(_))
+}
@@ -23,6 +23,55 @@ impl VecTools for Vec<u8> {
     }
 }
 
+/// Byte index where the word ending at `pos` starts
+pub fn word_start(line: &str, pos: usize, is_word_char: impl Fn(char) -> bool) -> usize {
+    line[..pos]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word_char(*c))
+        .last()
+        .map(|(idx, _)| idx)
+        .unwrap_or(pos)
+}
+
+/// 1 based line and column (in chars) of a byte offset
+pub fn line_col(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Byte offset of a 1 based line and column (in chars), clamped to the end of the line
+pub fn offset(src: &str, (line, col): (usize, usize)) -> usize {
+    let line_start: usize = src
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let text = src[line_start..].split('\n').next().unwrap_or_default();
+    line_start
+        + text
+            .char_indices()
+            .nth(col.saturating_sub(1))
+            .map_or(text.len(), |(idx, _)| idx)
+}
+
+/// Qualifier and first character of `word`, `Data.List.fldr` => `Data.List.f`
+pub fn fuzzy_anchor(word: &str) -> &str {
+    let name_start = word.rfind('.').map(|dot| dot + 1).unwrap_or(0);
+    match word[name_start..].chars().next() {
+        Some(c) => &word[..name_start + c.len_utf8()],
+        None => word,
+    }
+}
+
+/// Are all chars of `needle` found in order in `haystack`
+pub fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
 pub trait StringTools {
     fn split_non_alphanumeric<'a>(&'a self) -> Box<dyn Iterator<Item = String> + 'a>;
 }
@@ -88,3 +137,42 @@ impl<I: Iterator<Item = char>> Iterator for SplitNonAlphanumeric<I> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_start_stops_at_other_chars() {
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '.';
+        assert_eq!(word_start("map Data.List.fo", 16, is_word_char), 4);
+        assert_eq!(word_start("(λx", 4, is_word_char), 1);
+        assert_eq!(word_start("f (", 3, is_word_char), 3);
+        assert_eq!(word_start("foldr", 3, is_word_char), 0);
+    }
+
+    #[test]
+    fn offsets_and_line_columns_agree() {
+        let src = "ab\nλcd\n";
+        for at in [0, 1, 3, 5, 6] {
+            assert_eq!(offset(src, line_col(src, at)), at);
+        }
+        assert_eq!(offset(src, (2, 9)), 7);
+    }
+
+    #[test]
+    fn fuzzy_anchor_keeps_the_qualifier() {
+        assert_eq!(fuzzy_anchor("Data.List.fldr"), "Data.List.f");
+        assert_eq!(fuzzy_anchor("fldr"), "f");
+        assert_eq!(fuzzy_anchor("Data."), "Data.");
+        assert_eq!(fuzzy_anchor("éa"), "é");
+    }
+
+    #[test]
+    fn subsequences_keep_the_order() {
+        assert!(is_subsequence("fldr", "foldr"));
+        assert!(is_subsequence("", "foldr"));
+        assert!(!is_subsequence("rf", "foldr"));
+        assert!(!is_subsequence("foldrr", "foldr"));
+    }
+}